@@ -0,0 +1,55 @@
+// builds an opening book for `--book`, by searching every opening or from games the computer plays itself
+// run with `cargo run --release --example build_book <moves> <file> [--depth <n>] [--self-play <games>] [--creator-collapses] [--misere]`
+use qtictac_ai::book::Book;
+use qtictac_ai::search::Limits;
+use qtictac_ai::{CollapseChooser, RuleSet};
+use rand::{rngs::StdRng, SeedableRng};
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (plies, path) = match (args.get(1).and_then(|arg| arg.parse().ok()), args.get(2)) {
+        (Some(plies), Some(path)) => (plies, path),
+        _ => {
+            return println!(
+                "usage: build_book <moves> <file> [--depth <n>] [--self-play <games>] [--creator-collapses] [--misere]"
+            )
+        }
+    };
+    // the number after a flag, if it's there
+    let number = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|idx| args.get(idx + 1))
+            .and_then(|arg| arg.parse().ok())
+    };
+    let mut rules = RuleSet::default();
+    if args.iter().any(|arg| arg == "--creator-collapses") {
+        rules.collapse_chooser = CollapseChooser::Creator;
+    }
+    rules.misere = args.iter().any(|arg| arg == "--misere");
+    let limits = Limits {
+        depth: Some(number("--depth").unwrap_or(4)),
+        ..Limits::default()
+    };
+
+    let start = Instant::now();
+    let book = match number("--self-play") {
+        Some(games) => {
+            Book::from_self_play(rules, plies, games, limits, &mut StdRng::from_entropy())
+        }
+        None => Book::from_search(rules, plies, limits),
+    };
+    let book = match book {
+        Ok(book) => book,
+        Err(e) => return println!("{}", e),
+    };
+    println!(
+        "{} positions in {:.1}s",
+        book.len(),
+        start.elapsed().as_secs_f64()
+    );
+    if let Err(e) = book.save(path) {
+        println!("{}", e);
+    }
+}
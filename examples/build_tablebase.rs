@@ -0,0 +1,36 @@
+// solves every position with a few empty squares left and saves them for `--tablebase`
+// run with `cargo run --release --example build_tablebase <empty squares> <file> [--creator-collapses] [--misere]`
+use qtictac_ai::tablebase::Tablebase;
+use qtictac_ai::{CollapseChooser, RuleSet};
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (max_empty, path) = match (args.get(1).and_then(|arg| arg.parse().ok()), args.get(2)) {
+        (Some(max_empty), Some(path)) => (max_empty, path),
+        _ => {
+            return println!(
+                "usage: build_tablebase <empty squares> <file> [--creator-collapses] [--misere]"
+            )
+        }
+    };
+    let mut rules = RuleSet::default();
+    if args.iter().any(|arg| arg == "--creator-collapses") {
+        rules.collapse_chooser = CollapseChooser::Creator;
+    }
+    rules.misere = args.iter().any(|arg| arg == "--misere");
+
+    let start = Instant::now();
+    let tablebase = match Tablebase::generate(rules, max_empty) {
+        Ok(tablebase) => tablebase,
+        Err(e) => return println!("{}", e),
+    };
+    println!(
+        "solved {} positions in {:.1}s",
+        tablebase.len(),
+        start.elapsed().as_secs_f64()
+    );
+    if let Err(e) = tablebase.save(path) {
+        println!("{}", e);
+    }
+}
@@ -0,0 +1,41 @@
+// compares how many positions the search visits with and without move ordering
+// run with `cargo run --release --example move_ordering [depth]`
+use qtictac_ai::ordering::sample_positions;
+use qtictac_ai::search::Expectimax;
+
+fn main() {
+    let depth = std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(4);
+    println!(
+        "{:>8} {:>12} {:>12} {:>9} {:>14}",
+        "position", "unordered", "ordered", "saved", "first cutoffs"
+    );
+    let mut totals = (0, 0);
+    for (i, mut b) in sample_positions().into_iter().enumerate() {
+        let mut unordered = Expectimax::new().with_ordering(false);
+        let (plain_value, _) = unordered.search(&mut b, depth);
+        let mut ordered = Expectimax::new();
+        let (value, _) = ordered.search(&mut b, depth);
+        // pruning never changes the answer, only how long it takes to find
+        assert!((value - plain_value).abs() < 1e-9);
+        println!(
+            "{:>8} {:>12} {:>12} {:>8.1}% {:>13.1}%",
+            i + 1,
+            unordered.nodes,
+            ordered.nodes,
+            100.0 * (1.0 - ordered.nodes as f64 / unordered.nodes as f64),
+            100.0 * ordered.first_move_cutoffs as f64 / ordered.cutoffs.max(1) as f64,
+        );
+        totals.0 += unordered.nodes;
+        totals.1 += ordered.nodes;
+    }
+    println!(
+        "{:>8} {:>12} {:>12} {:>8.1}%",
+        "total",
+        totals.0,
+        totals.1,
+        100.0 * (1.0 - totals.1 as f64 / totals.0 as f64)
+    );
+}
@@ -0,0 +1,47 @@
+// times searches of the sample positions to a fixed depth with more and more threads
+// run with `cargo run --release --example parallel_speedup [depth]`
+use qtictac_ai::ordering::sample_positions;
+use qtictac_ai::parallel::ParallelSearch;
+use qtictac_ai::search::Limits;
+use std::time::Instant;
+
+fn main() {
+    let depth = std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(6);
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+    println!("{} cores, searching to depth {}", cores, depth);
+    println!(
+        "{:>7} {:>10} {:>12} {:>8}",
+        "threads", "seconds", "nodes", "speedup"
+    );
+    let limits = Limits {
+        depth: Some(depth),
+        ..Limits::default()
+    };
+    let mut single = None;
+    let mut threads = 1;
+    while threads <= cores.max(2) {
+        let search = ParallelSearch::new(threads);
+        let start = Instant::now();
+        let nodes: u64 = sample_positions()
+            .iter()
+            .map(|b| {
+                // each position starts from an empty table, like a new game would
+                search.table().clear();
+                search.search(b, limits).nodes
+            })
+            .sum();
+        let seconds = start.elapsed().as_secs_f64();
+        let single = *single.get_or_insert(seconds);
+        println!(
+            "{:>7} {:>10.3} {:>12} {:>7.2}x",
+            threads,
+            seconds,
+            nodes,
+            single / seconds
+        );
+        threads *= 2;
+    }
+}
@@ -0,0 +1,137 @@
+// proves or disproves that the player to move can force a win, for checking puzzles and endgames
+// run with `cargo run --release --example prove <moves> [--simultaneous tie|win] [--nodes <n>] [--measuring oldest|random] [--creator-collapses] [--misere]`
+// the moves lead up to the position, separated by commas: "15" is a quantum move in squares 1 and 5,
+// "5:1" collapses the cycle with move 1 in square 5, "m5:3" measures square 5 and finds move 3 there
+// (with `--measuring`), and "7" is a classical mark in the last square
+use qtictac_ai::proof::{ProofSearch, Simultaneous, Step, Verdict};
+use qtictac_ai::search::Limits;
+use qtictac_ai::{BoardState, CollapseChooser, Measuring, Move, RuleSet};
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let moves = match args.get(1) {
+        Some(moves) => moves,
+        None => {
+            return println!(
+                "usage: prove <moves> [--simultaneous tie|win] [--nodes <n>] [--measuring oldest|random] [--creator-collapses] [--misere]"
+            )
+        }
+    };
+    // the text after a flag, if it's there
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    let simultaneous = match flag("--simultaneous").map(String::as_str) {
+        Some("tie") | None => Simultaneous::Tie,
+        Some("win") => Simultaneous::Win,
+        Some(_) => return println!("--simultaneous needs to be followed by tie or win"),
+    };
+    let limits = Limits {
+        nodes: Some(
+            flag("--nodes")
+                .and_then(|arg| arg.parse().ok())
+                .unwrap_or(5_000_000),
+        ),
+        ..Limits::default()
+    };
+    // squares can only be measured with `--measuring`, which lets the proof measure them too
+    let measuring = match flag("--measuring").map(String::as_str) {
+        Some("oldest") => Measuring::Oldest,
+        Some("random") => Measuring::Random,
+        None => Measuring::Off,
+        Some(_) => return println!("--measuring needs to be followed by oldest or random"),
+    };
+    let mut rules = RuleSet {
+        measuring,
+        ..RuleSet::default()
+    };
+    if args.iter().any(|arg| arg == "--creator-collapses") {
+        rules.collapse_chooser = CollapseChooser::Creator;
+    }
+    rules.misere = args.iter().any(|arg| arg == "--misere");
+
+    let mut b = BoardState::new().with_rules(rules);
+    for text in moves
+        .split(',')
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+    {
+        if !play(&mut b, text) {
+            return println!("\"{}\" can't be played here", text);
+        }
+    }
+    if b.classic().game_is_over() {
+        return println!("the game is already over");
+    }
+
+    let start = Instant::now();
+    let proof = ProofSearch::new()
+        .with_simultaneous(simultaneous)
+        .prove(&b, limits);
+    let mover = qtictac_ai::MARKS[b.to_move() as usize];
+    match proof.verdict {
+        Verdict::Proven => println!("{} can force a win", mover),
+        Verdict::Disproven => println!("{} can't force a win", mover),
+        Verdict::Unknown => println!(
+            "not settled, proof number {} and disproof number {}",
+            proof.proof_number, proof.disproof_number
+        ),
+    }
+    let line: Vec<String> = proof.line.iter().map(|&step| describe(step)).collect();
+    println!("line: {}", line.join(", "));
+    println!(
+        "{} positions, {} expanded, in {:.1}s",
+        proof.nodes,
+        proof.expanded,
+        start.elapsed().as_secs_f64()
+    );
+}
+
+// plays one move written like the ones `main` takes, false if it can't be read or isn't valid
+fn play(b: &mut BoardState, text: &str) -> bool {
+    // squares and moves are counted from 1 like in the game
+    let number = |text: &str| text.parse::<u8>().ok().and_then(|n| n.checked_sub(1));
+    if let Some(rest) = text.strip_prefix('m') {
+        let mut parts = rest.split(':');
+        return match (parts.next().and_then(number), parts.next().and_then(number)) {
+            (Some(sq), Some(mov)) => b.measure(sq, mov).is_ok(),
+            _ => false,
+        };
+    }
+    let m = match text.split_once(':') {
+        Some((sq, mov)) => match (number(sq), number(mov)) {
+            (Some(sq), Some(mov)) => Move::Collapse { sq, mov },
+            _ => return false,
+        },
+        None => {
+            let squares: Option<Vec<u8>> = text
+                .chars()
+                .map(|c| c.to_digit(10).and_then(|d| (d as u8).checked_sub(1)))
+                .collect();
+            match squares.as_deref() {
+                Some(&[sq]) => Move::Classical(sq),
+                Some(&[sq1, sq2]) => Move::Quantum(sq1, sq2),
+                _ => return false,
+            }
+        }
+    };
+    if !b.is_valid(m) {
+        return false;
+    }
+    b.do_move(m);
+    true
+}
+
+// a step of the line, written the same way as the moves it takes
+fn describe(step: Step) -> String {
+    match step {
+        Step::Move(Move::Quantum(sq1, sq2)) => format!("{}{}", sq1 + 1, sq2 + 1),
+        Step::Move(Move::Collapse { sq, mov }) => format!("{}:{}", sq + 1, mov + 1),
+        Step::Move(Move::Classical(sq)) => format!("{}", sq + 1),
+        Step::Move(Move::Measure(sq)) => format!("m{}", sq + 1),
+        Step::Found { sq, mov } => format!("(found {}:{})", sq + 1, mov + 1),
+    }
+}
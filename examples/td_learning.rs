@@ -0,0 +1,77 @@
+// trains a TD learning player against itself, printing how it does against random moves as it learns
+// run with `cargo run --release --example td_learning <episodes> <file> [--every <n>] [--games <n>] [--load <file>] [--seed <n>]`
+use qtictac_ai::td::TdAgent;
+use qtictac_ai::RuleSet;
+use rand::{rngs::StdRng, SeedableRng};
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (episodes, path) = match (args.get(1).and_then(|arg| arg.parse().ok()), args.get(2)) {
+        (Some(episodes), Some(path)) => (episodes, path),
+        _ => {
+            return println!(
+                "usage: td_learning <episodes> <file> [--every <n>] [--games <n>] [--load <file>] [--seed <n>]"
+            )
+        }
+    };
+    // the text after a flag, if it's there
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    let number = |name: &str, default: u32| {
+        flag(name)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(default)
+    };
+    // how many training games between each line of the learning curve, and how many games each line plays
+    let every = number("--every", 1000).max(1);
+    let games = number("--games", 200);
+    // carries on training a table from an earlier run
+    let mut agent = match flag("--load").map(TdAgent::load) {
+        Some(Ok(agent)) => agent,
+        Some(Err(e)) => return println!("{}", e),
+        None => TdAgent::new(RuleSet::default()),
+    };
+    let mut rng = match flag("--seed").and_then(|arg| arg.parse().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // random moves win a lot of games by luck, so it plays the agent it started as too
+    let untrained = agent.clone();
+    let start = Instant::now();
+    println!("episodes  positions   wins  losses  ties  score  against the start");
+    let report = |episode: u32, agent: &TdAgent, rng: &mut StdRng| {
+        let tally = agent.play_random(games, rng);
+        let against = agent.play_agent(&untrained, games, rng);
+        let percent = |n: u32| 100.0 * f64::from(n) / f64::from(games.max(1));
+        println!(
+            "{:8}  {:9}  {:4.1}%  {:5.1}%  {:4.1}%  {:4.1}%  {:16.1}%",
+            episode,
+            agent.len(),
+            percent(tally.wins),
+            percent(tally.losses),
+            percent(tally.ties),
+            100.0 * tally.score(),
+            100.0 * against.score()
+        );
+    };
+    report(0, &agent, &mut rng);
+    for episode in 1..=episodes {
+        agent.train(&mut rng);
+        if episode % every == 0 || episode == episodes {
+            report(episode, &agent, &mut rng);
+        }
+    }
+    println!(
+        "trained for {} episodes in {:.1}s",
+        episodes,
+        start.elapsed().as_secs_f64()
+    );
+    if let Err(e) = agent.save(path) {
+        println!("{}", e);
+    }
+}
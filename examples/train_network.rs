@@ -0,0 +1,120 @@
+// trains a network evaluator on self-play positions, then plays it against the hand-written evaluation
+// run with `cargo run --release --example train_network <games> <file> [--hidden <n>] [--epochs <n>] [--depth <n>] [--tablebase <file>] [--match <pairs>] [--seed <n>]`
+use qtictac_ai::eval::Weights;
+use qtictac_ai::network::{encode, Example, Network};
+use qtictac_ai::parallel::ParallelSearch;
+use qtictac_ai::search::{result, Limits};
+use qtictac_ai::selfplay::{play_game, play_match, random_opening};
+use qtictac_ai::tablebase::Tablebase;
+use qtictac_ai::RuleSet;
+use rand::seq::SliceRandom;
+use rand::{rngs::StdRng, SeedableRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+// random moves at the start of each game, so the games aren't all the same
+const OPENING_MOVES: u32 = 2;
+const RATE: f64 = 0.01;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (games, path) = match (args.get(1).and_then(|arg| arg.parse().ok()), args.get(2)) {
+        (Some(games), Some(path)) => (games, path),
+        _ => {
+            return println!(
+                "usage: train_network <games> <file> [--hidden <n>] [--epochs <n>] [--depth <n>] [--tablebase <file>] [--match <pairs>] [--seed <n>]"
+            )
+        }
+    };
+    // the text after a flag, if it's there
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    let number = |name: &str, default: u32| {
+        flag(name)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(default)
+    };
+    let limits = Limits {
+        depth: Some(number("--depth", 3)),
+        ..Limits::default()
+    };
+    // positions it has are labelled with their exact result instead of how the game went
+    let tablebase = match flag("--tablebase").map(Tablebase::load) {
+        Some(Ok(tablebase)) => Some(tablebase),
+        Some(Err(e)) => return println!("{}", e),
+        None => None,
+    };
+    let mut rng = match flag("--seed").and_then(|arg| arg.parse().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let rules = RuleSet::default();
+
+    let start = Instant::now();
+    let engine = ParallelSearch::new(0).with_evaluation(Weights::default());
+    let mut examples: Vec<Example> = Vec::new();
+    let mut solved = 0;
+    for _ in 0..games {
+        let opening = random_opening(rules, OPENING_MOVES, &mut rng);
+        let game = play_game(opening, [&engine, &engine], limits, &mut rng);
+        for b in game.positions.iter().filter(|b| result(*b).is_none()) {
+            let label = match tablebase.as_ref().and_then(|tb| tb.probe(b)) {
+                Some(solution) => {
+                    solved += 1;
+                    f64::from(solution.value)
+                }
+                None => game.result,
+            };
+            examples.push((encode(b), label));
+        }
+    }
+    println!(
+        "{} positions ({} from the tablebase) from {} games in {:.1}s",
+        examples.len(),
+        solved,
+        games,
+        start.elapsed().as_secs_f64()
+    );
+
+    // a tenth of the positions are kept out of training, to see if it learned more than those positions
+    examples.shuffle(&mut rng);
+    let (test, train) = examples.split_at(examples.len() / 10);
+    let mut network = Network::new(9, number("--hidden", 32) as usize, &mut rng);
+    println!(
+        "error before training: {:.4} on training positions, {:.4} on the rest",
+        network.error(train),
+        network.error(test)
+    );
+    let start = Instant::now();
+    let error = network.train(train, number("--epochs", 50), RATE, &mut rng);
+    println!(
+        "error after training: {:.4} on training positions, {:.4} on the rest ({:.1}s)",
+        error,
+        network.error(test),
+        start.elapsed().as_secs_f64()
+    );
+    if let Err(e) = network.save(path) {
+        return println!("{}", e);
+    }
+
+    let learned = ParallelSearch::new(0).with_evaluation(Arc::new(network));
+    let tally = play_match(
+        &learned,
+        &engine,
+        rules,
+        number("--match", 50),
+        OPENING_MOVES,
+        limits,
+        &mut rng,
+    );
+    println!(
+        "network against the default weights: {} wins, {} losses, {} ties ({:.1}%)",
+        tally.wins,
+        tally.losses,
+        tally.ties,
+        100.0 * tally.score()
+    );
+}
@@ -0,0 +1,103 @@
+// fits the evaluation weights to how self-play games end, then plays the new weights against the old ones
+// run with `cargo run --release --example tune_weights <games> <file> [--depth <n>] [--start <weights file>] [--match <pairs>] [--seed <n>]`
+use qtictac_ai::eval::{self, Sample, Weights};
+use qtictac_ai::parallel::ParallelSearch;
+use qtictac_ai::search::{result, Limits};
+use qtictac_ai::selfplay::{play_game, play_match, random_opening};
+use qtictac_ai::RuleSet;
+use rand::{rngs::StdRng, SeedableRng};
+use std::time::Instant;
+
+// random moves at the start of each game, so the games aren't all the same
+const OPENING_MOVES: u32 = 2;
+const STEPS: u32 = 2000;
+const RATE: f64 = 0.05;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let (games, path) = match (args.get(1).and_then(|arg| arg.parse().ok()), args.get(2)) {
+        (Some(games), Some(path)) => (games, path),
+        _ => {
+            return println!(
+                "usage: tune_weights <games> <file> [--depth <n>] [--start <weights file>] [--match <pairs>] [--seed <n>]"
+            )
+        }
+    };
+    // the text after a flag, if it's there
+    let flag = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|idx| args.get(idx + 1))
+    };
+    let limits = Limits {
+        depth: Some(
+            flag("--depth")
+                .and_then(|arg| arg.parse().ok())
+                .unwrap_or(3),
+        ),
+        ..Limits::default()
+    };
+    let before = match flag("--start").map(Weights::load) {
+        Some(Ok(weights)) => weights,
+        Some(Err(e)) => return println!("{}", e),
+        None => Weights::default(),
+    };
+    let pairs = flag("--match")
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(50);
+    let mut rng = match flag("--seed").and_then(|arg| arg.parse().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let rules = RuleSet::default();
+
+    // every position of every game, labelled with how its game ended
+    let start = Instant::now();
+    let engine = ParallelSearch::new(0).with_evaluation(before);
+    let mut samples: Vec<Sample> = Vec::new();
+    for _ in 0..games {
+        let opening = random_opening(rules, OPENING_MOVES, &mut rng);
+        let game = play_game(opening, [&engine, &engine], limits, &mut rng);
+        samples.extend(
+            game.positions
+                .iter()
+                .filter(|b| result(*b).is_none())
+                .map(|b| (eval::features(b), game.result)),
+        );
+    }
+    println!(
+        "{} positions from {} games in {:.1}s",
+        samples.len(),
+        games,
+        start.elapsed().as_secs_f64()
+    );
+
+    let after = eval::tune(&samples, before, STEPS, RATE);
+    println!(
+        "prediction error {:.4} before, {:.4} after",
+        eval::prediction_error(&samples, before),
+        eval::prediction_error(&samples, after)
+    );
+    print!("{}", after);
+    if let Err(e) = after.save(path) {
+        return println!("{}", e);
+    }
+
+    let tuned = ParallelSearch::new(0).with_evaluation(after);
+    let tally = play_match(
+        &tuned,
+        &engine,
+        rules,
+        pairs,
+        OPENING_MOVES,
+        limits,
+        &mut rng,
+    );
+    println!(
+        "tuned against the starting weights: {} wins, {} losses, {} ties ({:.1}%)",
+        tally.wins,
+        tally.losses,
+        tally.ties,
+        100.0 * tally.score()
+    );
+}
@@ -0,0 +1,432 @@
+use crate::file::{FileError, Layout};
+use crate::parallel::ParallelSearch;
+use crate::search::{result, Limits};
+use crate::table::{decode, encode};
+use crate::{BoardState, Move, RuleSet, Topology};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::{error, fmt, path::Path};
+
+/// A move the book knows about, and how well it's done
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BookMove {
+    pub m: Move,
+    // the average of every score recorded for the move, from X's point of view like search values
+    pub value: f64,
+    // how many scores that is, one for a searched move or the number of games it was played in
+    pub count: u32,
+}
+
+/// Scored moves for positions near the start of the game, for playing without searching
+///
+/// Made by `from_search`, which searches every move of every position a few moves in, or by
+/// `from_self_play`, which plays games from random openings and scores the opening moves by how
+/// the games ended. Positions are stored turned and flipped the same way as every board they're
+/// symmetric to, so the handful of different first moves only take a handful of entries. Only
+/// boards on the normal 3x3 board played by the book's rules are looked up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Book {
+    rules: RuleSet,
+    // by the key of each position turned to face the way `BoardState::canonical_key` picks, with its moves turned too
+    positions: HashMap<u64, Vec<BookMove>>,
+    symmetries: Vec<Vec<u8>>,
+}
+
+const LAYOUT: Layout = Layout {
+    kind: "opening book",
+    magic: b"QTBK",
+    version: 1,
+    header: 13,
+    record: 22,
+};
+
+impl Book {
+    // a book with nothing in it yet
+    pub fn new(rules: RuleSet) -> Self {
+        Book {
+            rules,
+            positions: HashMap::new(),
+            symmetries: Topology::classic().symmetries(),
+        }
+    }
+
+    // searches every move in every position up to `plies` moves into the game, each one with `limits`
+    // moves that lead to the same position as one already searched, turned or flipped, are skipped
+    pub fn from_search(rules: RuleSet, plies: u32, limits: Limits) -> Result<Self, BookError> {
+        if rules.players != 2 {
+            return Err(BookError::Rules);
+        }
+        let mut book = Book::new(rules);
+        let engine = ParallelSearch::new(0);
+        let mut frontier = vec![BoardState::new().with_rules(rules)];
+        let mut seen = HashSet::new();
+        let mut moves = Vec::new();
+        for _ in 0..plies {
+            let mut next = Vec::new();
+            for b in frontier {
+                b.valid_moves(&mut moves);
+                let mut children = HashSet::new();
+                for &m in moves.iter() {
+                    let mut child = b.clone();
+                    child.do_move(m);
+                    let (key, _) = child.canonical_key(&book.symmetries);
+                    if !children.insert(key) {
+                        continue;
+                    }
+                    let value = match result(&child) {
+                        Some(value) => value,
+                        None => engine.search(&child, limits).value,
+                    };
+                    book.record(&b, m, value);
+                    // nobody picks how a measured cycle collapses, so there's nothing to look up there
+                    if seen.insert(key)
+                        && !child.classic().game_is_over()
+                        && child.collapse_chances().is_none()
+                    {
+                        next.push(child);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        Ok(book)
+    }
+
+    // plays `games` games where the first `plies` moves are picked at random and the rest are
+    // searched with `limits`, then scores each of the random moves with how its game ended
+    pub fn from_self_play<R: Rng>(
+        rules: RuleSet,
+        plies: u32,
+        games: u32,
+        limits: Limits,
+        rng: &mut R,
+    ) -> Result<Self, BookError> {
+        if rules.players != 2 {
+            return Err(BookError::Rules);
+        }
+        let mut book = Book::new(rules);
+        let engine = ParallelSearch::new(0);
+        let mut moves = Vec::new();
+        for _ in 0..games {
+            let mut b = BoardState::new().with_rules(rules);
+            let mut opening = Vec::new();
+            while !b.classic().game_is_over() {
+                if b.collapse_chances().is_some() {
+                    b.measure_cycle(rng).expect("there's a cycle to measure");
+                    continue;
+                }
+                let m = if opening.len() < plies as usize {
+                    b.valid_moves(&mut moves);
+                    let &m = moves
+                        .choose(rng)
+                        .expect("a game that isn't over has a move");
+                    opening.push((b.clone(), m));
+                    m
+                } else {
+                    match engine.search(&b, limits).best {
+                        Some(m) => m,
+                        None => break,
+                    }
+                };
+                match m {
+                    Move::Measure(sq) => {
+                        b.measure_randomly(sq, rng)
+                            .expect("the search measures valid squares");
+                    }
+                    m => b.do_move(m),
+                }
+            }
+            let value = result(&b).unwrap_or(0.0);
+            for (b, m) in opening {
+                book.record(&b, m, value);
+            }
+            engine.table().clear();
+        }
+        Ok(book)
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    // the number of positions with moves in the book
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // adds `value` to the scores of `m` in `b`, which is averaged with any it already had
+    pub fn record<const N: usize>(&mut self, b: &BoardState<N>, m: Move, value: f64) {
+        debug_assert!(std::ptr::eq(b.topology(), Topology::classic()) && b.rules() == self.rules);
+        let (key, map) = b.canonical_key(&self.symmetries);
+        let m = turn(m, map);
+        let moves = self.positions.entry(key).or_default();
+        match moves.iter_mut().find(|known| known.m == m) {
+            Some(known) => {
+                known.count += 1;
+                known.value += (value - known.value) / f64::from(known.count);
+            }
+            None => moves.push(BookMove { m, value, count: 1 }),
+        }
+    }
+
+    // every move the book has for `b`, turned to fit it, empty if `b` isn't in the book
+    pub fn moves<const N: usize>(&self, b: &BoardState<N>) -> Vec<BookMove> {
+        if !std::ptr::eq(b.topology(), Topology::classic()) || b.rules() != self.rules {
+            return Vec::new();
+        }
+        let (key, map) = b.canonical_key(&self.symmetries);
+        let back = inverse(map);
+        self.positions.get(&key).map_or(Vec::new(), |moves| {
+            moves
+                .iter()
+                .map(|&known| BookMove {
+                    m: turn(known.m, &back),
+                    ..known
+                })
+                .collect()
+        })
+    }
+
+    // the book move with the best score for the player to move in `b`
+    pub fn best_move<const N: usize>(&self, b: &BoardState<N>) -> Option<BookMove> {
+        let sign = if b.to_move() == 0 { 1.0 } else { -1.0 };
+        self.moves(b)
+            .into_iter()
+            .max_by(|a, b| (sign * a.value).total_cmp(&(sign * b.value)))
+    }
+
+    // writes the book in the format `from_bytes` reads:
+    // "QTBK", a version byte, the rules as four bytes (the collapse rule, its measurement weight, the
+    // measuring rule and whether it's misère), the number of moves as 4 little-endian bytes, and then
+    // every move as the 8 byte key of its position, 2 bytes for the move, its count and its value
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut keys: Vec<_> = self.positions.keys().copied().collect();
+        keys.sort_unstable();
+        let count: usize = self.positions.values().map(Vec::len).sum();
+        let mut bytes = LAYOUT.start(count);
+        bytes.extend_from_slice(&self.rules.to_bytes());
+        bytes.extend_from_slice(&(count as u32).to_le_bytes());
+        for key in keys {
+            for known in self.positions[&key].iter() {
+                bytes.extend_from_slice(&key.to_le_bytes());
+                bytes.extend_from_slice(&encode(Some(known.m)).to_le_bytes());
+                bytes.extend_from_slice(&known.count.to_le_bytes());
+                bytes.extend_from_slice(&known.value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BookError> {
+        LAYOUT.check(bytes)?;
+        let mut book = Book::new(LAYOUT.rules(bytes)?);
+        for record in LAYOUT.records(bytes, LAYOUT.count(bytes))? {
+            let mut key = [0; 8];
+            key.copy_from_slice(&record[..8]);
+            let m = decode(u16::from_le_bytes([record[8], record[9]]))
+                .ok_or_else(|| LAYOUT.format("a position is missing its move"))?;
+            let mut count = [0; 4];
+            count.copy_from_slice(&record[10..14]);
+            let mut value = [0; 8];
+            value.copy_from_slice(&record[14..]);
+            book.positions
+                .entry(u64::from_le_bytes(key))
+                .or_default()
+                .push(BookMove {
+                    m,
+                    value: f64::from_le_bytes(value),
+                    count: u32::from_le_bytes(count),
+                });
+        }
+        Ok(book)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BookError> {
+        Ok(LAYOUT.save(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BookError> {
+        Self::from_bytes(&LAYOUT.load(path)?)
+    }
+}
+
+/// Why a book couldn't be made or loaded
+#[derive(Debug)]
+pub enum BookError {
+    File(FileError),
+    // the search only plays two player games
+    Rules,
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::File(e) => e.fmt(f),
+            BookError::Rules => write!(f, "opening books can only be made for two players"),
+        }
+    }
+}
+
+impl error::Error for BookError {}
+
+impl From<FileError> for BookError {
+    fn from(e: FileError) -> Self {
+        BookError::File(e)
+    }
+}
+
+// `m` with each of its squares moved to where `map` sends it
+fn turn(m: Move, map: &[u8]) -> Move {
+    let to = |sq: u8| map[sq as usize];
+    match m {
+        // the lower square first, like `valid_moves` gives them
+        Move::Quantum(sq1, sq2) => Move::Quantum(to(sq1).min(to(sq2)), to(sq1).max(to(sq2))),
+        Move::Collapse { sq, mov } => Move::Collapse { sq: to(sq), mov },
+        Move::Classical(sq) => Move::Classical(to(sq)),
+        Move::Measure(sq) => Move::Measure(to(sq)),
+    }
+}
+
+// the map that undoes `map`
+fn inverse(map: &[u8]) -> Vec<u8> {
+    let mut back = vec![0; map.len()];
+    for (sq, &to) in map.iter().enumerate() {
+        back[to as usize] = sq as u8;
+    }
+    back
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CollapseChooser, Measuring};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn symmetric_positions_share_moves() {
+        let mut book = Book::new(RuleSet::default());
+        let mut b = BoardState::new();
+        b.do_move(Move::Quantum(0, 1));
+        book.record(&b, Move::Quantum(1, 4), 0.5);
+        // the same position flipped left to right
+        let mut flipped = BoardState::new();
+        flipped.do_move(Move::Quantum(1, 2));
+        let moves = book.moves(&flipped);
+        assert_eq!(
+            moves,
+            vec![BookMove {
+                m: Move::Quantum(1, 4),
+                value: 0.5,
+                count: 1
+            }]
+        );
+        // and turned so the marks are down the left side
+        let mut turned = BoardState::new();
+        turned.do_move(Move::Quantum(0, 3));
+        assert_eq!(book.moves(&turned)[0].m, Move::Quantum(3, 4));
+        assert_eq!(book.len(), 1);
+        // a board that isn't symmetric to it isn't in the book
+        let mut other = BoardState::new();
+        other.do_move(Move::Quantum(0, 4));
+        assert!(book.moves(&other).is_empty());
+        // and neither is the same board played by other rules
+        let misere = RuleSet {
+            misere: true,
+            ..RuleSet::default()
+        };
+        assert!(book.moves(&b.clone().with_rules(misere)).is_empty());
+    }
+
+    #[test]
+    fn best_move_for_each_player() {
+        let mut book = Book::new(RuleSet::default());
+        let mut b = BoardState::new();
+        book.record(&b, Move::Quantum(0, 4), 0.25);
+        book.record(&b, Move::Quantum(0, 4), 0.75);
+        book.record(&b, Move::Quantum(0, 1), 0.0);
+        let best = book.best_move(&b).unwrap();
+        assert_eq!(
+            best,
+            BookMove {
+                m: Move::Quantum(0, 4),
+                value: 0.5,
+                count: 2
+            }
+        );
+        b.do_move(Move::Quantum(0, 4));
+        book.record(&b, Move::Quantum(1, 2), 0.5);
+        book.record(&b, Move::Quantum(2, 6), -0.5);
+        assert_eq!(book.best_move(&b).unwrap().m, Move::Quantum(2, 6));
+    }
+
+    #[test]
+    fn built_from_search() {
+        let limits = Limits {
+            depth: Some(1),
+            ..Limits::default()
+        };
+        let book = Book::from_search(RuleSet::default(), 2, limits).unwrap();
+        // once they're turned and flipped, the 36 first moves only make 8 different boards: two
+        // corners next to each other or across, a corner and the edge next to it or across from it,
+        // a corner and the center, two edges next to each other or across, and an edge and the center
+        let b = BoardState::new();
+        let first = book.moves(&b);
+        assert_eq!(first.len(), 8);
+        assert_eq!(book.len(), 1 + first.len());
+        for known in first {
+            assert!(b.is_valid(known.m));
+            let mut after = b.clone();
+            after.do_move(known.m);
+            assert!(book
+                .best_move(&after)
+                .is_some_and(|best| after.is_valid(best.m)));
+        }
+        let three = RuleSet {
+            players: 3,
+            ..RuleSet::default()
+        };
+        assert!(matches!(
+            Book::from_search(three, 1, limits),
+            Err(BookError::Rules)
+        ));
+    }
+
+    #[test]
+    fn built_from_self_play() {
+        let limits = Limits {
+            depth: Some(1),
+            ..Limits::default()
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let book = Book::from_self_play(RuleSet::default(), 2, 6, limits, &mut rng).unwrap();
+        let b = BoardState::new();
+        let first = book.moves(&b);
+        assert_eq!(first.iter().map(|known| known.count).sum::<u32>(), 6);
+        assert!(first.iter().all(|known| known.value.abs() <= 1.0));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let rules = RuleSet {
+            collapse_chooser: CollapseChooser::Measurement { weight: 70 },
+            measuring: Measuring::Random,
+            ..RuleSet::default()
+        };
+        let mut book = Book::new(rules);
+        let mut b = BoardState::new().with_rules(rules);
+        book.record(&b, Move::Quantum(2, 4), -0.125);
+        b.do_move(Move::Quantum(2, 4));
+        book.record(&b, Move::Measure(4), 1.0);
+        book.record(&b, Move::Quantum(0, 8), 0.0);
+        let bytes = book.to_bytes();
+        assert_eq!(bytes.len(), LAYOUT.header + 3 * LAYOUT.record);
+        assert_eq!(Book::from_bytes(&bytes).unwrap(), book);
+        assert!(Book::from_bytes(&bytes[..20]).is_err());
+        assert!(Book::from_bytes(b"not an opening book").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::graph::BoardGraph;
+use crate::QuantumBoardState;
+use smallvec::SmallVec;
+use std::fmt;
+
+/// One step of a collapse: the square that became classical and the move that ended up in it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Assignment {
+    pub sq: u8,
+    pub mov: u8,
+}
+
+/// The squares made classical by a collapse, in the order the resolution reached them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CollapseTrace {
+    assignments: SmallVec<[Assignment; 9]>,
+}
+
+impl CollapseTrace {
+    pub fn iter(&self) -> impl Iterator<Item = &Assignment> {
+        self.assignments.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.assignments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assignments.is_empty()
+    }
+}
+
+/// Why a collapse couldn't be done
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollapseError {
+    // there's no cycle on the board to collapse
+    NoCycle,
+    // the square given isn't part of the cycle
+    NotInCycle(u8),
+    // the move given isn't one of the cycle's moves in that square
+    NotCycleMove { sq: u8, mov: u8 },
+    // a square can't be measured while there's a cycle waiting to be collapsed
+    PendingCycle,
+    // the move given has no mark in the square being measured
+    NotInSquare { sq: u8, mov: u8 },
+    // the rules don't allow measuring, or there's nothing in the square to find
+    CannotMeasure(u8),
+    // every move number left is needed for the marks still to be made, so nothing more can be measured
+    NoMovesLeft,
+    // the resolution reached a square where there wasn't exactly one move that could stay
+    // this can only happen if the quantum board and the graph disagree
+    Corrupt { sq: u8, mask: u32 },
+}
+
+impl fmt::Display for CollapseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CollapseError::NoCycle => write!(f, "there is no cycle to collapse"),
+            CollapseError::NotInCycle(sq) => write!(f, "square {} is not in the cycle", sq),
+            CollapseError::NotCycleMove { sq, mov } => {
+                write!(f, "move {} is not part of the cycle in square {}", mov, sq)
+            }
+            CollapseError::PendingCycle => write!(f, "the cycle has to be collapsed first"),
+            CollapseError::NotInSquare { sq, mov } => {
+                write!(f, "move {} has no mark in square {}", mov, sq)
+            }
+            CollapseError::CannotMeasure(sq) => write!(f, "square {} can't be measured", sq),
+            CollapseError::NoMovesLeft => {
+                write!(f, "there are no move numbers left to measure with")
+            }
+            CollapseError::Corrupt { sq, mask } => write!(
+                f,
+                "square {} can't be resolved from moves {:032b}",
+                sq, mask
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CollapseError {}
+
+// propagates putting `mov` in `start` through everything entangled with it, clearing the squares
+// it resolves out of `q` and `g` as it goes
+// `g` must already be a tree around `start`, i.e. the cycle has been cut
+pub(crate) fn resolve<const N: usize>(
+    start: u8,
+    mov: u8,
+    q: &mut QuantumBoardState<N>,
+    g: &mut BoardGraph<N>,
+) -> Result<CollapseTrace, CollapseError> {
+    let mut trace = CollapseTrace::default();
+    // squares still to resolve, each with the moves that were eliminated from the square before it
+    // exactly one of those has its other half here, and that's the one that stays
+    let mut work: SmallVec<[(u8, u32); 9]> = SmallVec::new();
+    work.push((start, QuantumBoardState::mask(mov)));
+    while let Some((sq, last_mask)) = work.pop() {
+        let decision_mask = q.mask_in(sq) & last_mask;
+        if decision_mask.count_ones() != 1 {
+            return Err(CollapseError::Corrupt {
+                sq,
+                mask: decision_mask,
+            });
+        }
+        trace.assignments.push(Assignment {
+            sq,
+            mov: decision_mask.trailing_zeros() as u8,
+        });
+
+        let next_last_mask = q.mask_in(sq) & !decision_mask;
+        let edges = g.edges()[sq as usize];
+        g.clear_vert(sq);
+        q.clear(sq);
+        // pushed in reverse so they come off the stack lowest square first, depth first
+        for next in (0..N as u8).rev().filter(|&next| edges[next as usize] > 0) {
+            work.push((next, next_last_mask));
+        }
+    }
+    Ok(trace)
+}
@@ -0,0 +1,364 @@
+use crate::file::FileError;
+use crate::network::Network;
+use crate::search::result;
+use crate::{BoardState, Topology};
+use std::sync::Arc;
+use std::{fmt, fs, path::Path};
+
+/// The names of the features `evaluate` adds up, in the order of `Weights` and `Score::terms`
+///
+/// Each one is from X's point of view, so it's positive when it's good for X if its weight is:
+///
+/// - `open_lines`: lines without any of the other player's classical marks, X's minus O's
+/// - `line_progress`: the square of the number of classical marks in each of those lines, so two
+///   in a row counts for 4, X's minus O's
+/// - `quantum_marks`: quantum marks in squares of those lines, X's minus O's
+/// - `cycle_threats`: pairs of squares that are already entangled, so a quantum move in both
+///   would close a cycle, for the player to move (negative when it's O)
+/// - `entanglement`: the number of squares in the biggest group of entangled squares, for the
+///   player to move, since whoever closes its cycle decides a lot of the board at once
+/// - `collapse_choice`: 1 if X picks how the cycle on the board collapses, -1 if O does
+/// - `tempo`: 1 if it's X's move, -1 if it's O's
+pub const FEATURES: [&str; FEATURE_COUNT] = [
+    "open_lines",
+    "line_progress",
+    "quantum_marks",
+    "cycle_threats",
+    "entanglement",
+    "collapse_choice",
+    "tempo",
+];
+pub const FEATURE_COUNT: usize = 7;
+
+// evaluations are scaled into this, so they're never as good or as bad as a finished game
+pub const EVAL_LIMIT: f64 = 0.99;
+
+/// How good a position looks for X without searching it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Score {
+    // from X's point of view like search values, strictly between -1 and 1 unless the game is over
+    pub value: f64,
+    // each feature times its weight, in the order of `FEATURES`, which add up to the value before
+    // it's squashed between -1 and 1
+    pub terms: [f64; FEATURE_COUNT],
+}
+
+/// How much each feature of `FEATURES` counts towards an evaluation
+///
+/// `Weights::default()` is the same as `weights/default.txt`, and `Weights::load` reads other
+/// files in that format so the evaluation can be changed without rebuilding.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Weights(pub [f64; FEATURE_COUNT]);
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights([0.05, 0.1, 0.02, -0.02, 0.0, 0.3, 0.05])
+    }
+}
+
+/// What a search values positions with when it can't see the end of the game
+#[derive(Clone, Debug)]
+pub enum Evaluator {
+    // the hand-written features, weighted
+    Weights(Weights),
+    // a network trained on played out positions, see `Network`
+    Network(Arc<Network>),
+}
+
+impl Evaluator {
+    // the value of `b` from X's point of view, strictly between -1 and 1 unless the game is over
+    pub fn value<const N: usize>(&self, b: &BoardState<N>) -> f64 {
+        match self {
+            Evaluator::Weights(weights) => weights.evaluate(b).value,
+            Evaluator::Network(network) => network.evaluate(b),
+        }
+    }
+}
+
+impl From<Weights> for Evaluator {
+    fn from(weights: Weights) -> Self {
+        Evaluator::Weights(weights)
+    }
+}
+
+impl From<Arc<Network>> for Evaluator {
+    fn from(network: Arc<Network>) -> Self {
+        Evaluator::Network(network)
+    }
+}
+
+// the static evaluation of `b` with the default weights, see `Weights::evaluate`
+pub fn evaluate<const N: usize>(b: &BoardState<N>) -> Score {
+    Weights::default().evaluate(b)
+}
+
+// the features of `b` in the order of `FEATURES`, before they're weighted
+// only X and O count, so this is for two player games
+pub fn features<const N: usize>(b: &BoardState<N>) -> [f64; FEATURE_COUNT] {
+    let cells = b.cells();
+    let (x, o) = (b.classic().squares_of(0), b.classic().squares_of(1));
+    // how many quantum marks each player has in each square
+    let mut quantum = [[0u32; 2]; Topology::MAX_CELLS];
+    for mov in 0..b.next_mov() {
+        let player = b.player_of(mov) as usize;
+        for sq in (0..cells).filter(|&sq| b.quantum().is(mov, sq)) {
+            if player < 2 {
+                quantum[sq as usize][player] += 1;
+            }
+        }
+    }
+    let (mut open, mut progress, mut marks) = ([0.0; 2], [0.0; 2], [0.0; 2]);
+    for &line in b.topology().lines() {
+        for (player, &(mine, theirs)) in [(x, o), (o, x)].iter().enumerate() {
+            if line & theirs > 0 {
+                continue;
+            }
+            open[player] += 1.0;
+            progress[player] += f64::from((line & mine).count_ones().pow(2));
+            marks[player] += (0..cells)
+                .filter(|&sq| line & 1 << sq > 0)
+                .map(|sq| f64::from(quantum[sq as usize][player]))
+                .sum::<f64>();
+        }
+    }
+    let mover = if b.to_move() == 0 { 1.0 } else { -1.0 };
+    // while a cycle waits to collapse, nobody can make a quantum move to close another one
+    let (threats, biggest) = if b.has_cycle() {
+        (0.0, 0.0)
+    } else {
+        let sizes = b.graph().components().into_iter().map(u32::count_ones);
+        sizes.fold((0.0, 0.0), |(threats, biggest): (f64, f64), size| {
+            let size = f64::from(size);
+            (threats + size * (size - 1.0) / 2.0, biggest.max(size))
+        })
+    };
+    let choice = match (b.has_cycle(), b.collapse_chooser()) {
+        (true, Some(0)) => 1.0,
+        (true, Some(_)) => -1.0,
+        _ => 0.0,
+    };
+    [
+        open[0] - open[1],
+        progress[0] - progress[1],
+        marks[0] - marks[1],
+        mover * threats,
+        mover * biggest,
+        choice,
+        mover,
+    ]
+}
+
+impl Weights {
+    // adds up the weighted features of `b` and squashes them between -`EVAL_LIMIT` and `EVAL_LIMIT`
+    // finished games are worth what the search would give them, with no terms
+    pub fn evaluate<const N: usize>(&self, b: &BoardState<N>) -> Score {
+        let mut terms = [0.0; FEATURE_COUNT];
+        if let Some(value) = result(b) {
+            return Score { value, terms };
+        }
+        for ((term, feature), weight) in terms.iter_mut().zip(features(b)).zip(self.0) {
+            *term = feature * weight;
+        }
+        Score {
+            value: EVAL_LIMIT * terms.iter().sum::<f64>().tanh(),
+            terms,
+        }
+    }
+
+    // reads weights from lines with a feature's name and its weight
+    //
+    //     # anything after a # is ignored
+    //     open_lines 0.05
+    //     tempo -0.1
+    //
+    // features that aren't given keep their default weight
+    pub fn parse(text: &str) -> Result<Self, FileError> {
+        let mut weights = Weights::default();
+        for (idx, line) in text.lines().enumerate() {
+            let err = |message: &str| FileError::Parse {
+                line: idx + 1,
+                message: message.to_string(),
+            };
+            let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            let (name, weight) = match words[..] {
+                [] => continue,
+                [name, weight] => (name, weight),
+                _ => return Err(err("expected a feature and its weight")),
+            };
+            let feature = FEATURES
+                .iter()
+                .position(|&feature| feature == name)
+                .ok_or_else(|| err("unknown feature"))?;
+            weights.0[feature] = match weight.parse::<f64>() {
+                Ok(weight) if weight.is_finite() => weight,
+                _ => return Err(err("the weight isn't a number")),
+            };
+        }
+        Ok(weights)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FileError> {
+        let text = fs::read_to_string(path).map_err(|e| FileError::io("weights", e))?;
+        Self::parse(&text)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FileError> {
+        fs::write(path, self.to_string()).map_err(|e| FileError::io("weights", e))
+    }
+}
+
+/// A position's features and how its game ended, for fitting weights to
+pub type Sample = ([f64; FEATURE_COUNT], f64);
+
+// the mean squared difference between each sample's evaluation with `weights` and its result
+pub fn prediction_error(samples: &[Sample], weights: Weights) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|(features, result)| (predict(features, weights) - result).powi(2))
+        .sum();
+    total / samples.len().max(1) as f64
+}
+
+// fits weights to samples, starting from `start`, by taking `steps` steps of gradient descent on
+// `prediction_error`. This is Texel tuning: a position's evaluation is a guess at how its game
+// ends, so the best weights are the ones that guess best.
+// Features can be much bigger than each other, so each weight's step is divided by how big its
+// feature usually is, and `rate` works for all of them.
+pub fn tune(samples: &[Sample], start: Weights, steps: u32, rate: f64) -> Weights {
+    let count = samples.len().max(1) as f64;
+    let mut size = [0.0; FEATURE_COUNT];
+    for (features, _) in samples.iter() {
+        for (size, feature) in size.iter_mut().zip(features) {
+            *size += feature * feature / count;
+        }
+    }
+    let mut weights = start;
+    for _ in 0..steps {
+        let mut gradient = [0.0; FEATURE_COUNT];
+        for (features, result) in samples.iter() {
+            let squashed = dot(features, weights).tanh();
+            // the derivative of the error with respect to the sum of the weighted features
+            let slope =
+                2.0 * (EVAL_LIMIT * squashed - result) * EVAL_LIMIT * (1.0 - squashed * squashed);
+            for (gradient, feature) in gradient.iter_mut().zip(features) {
+                *gradient += slope * feature / count;
+            }
+        }
+        for ((weight, gradient), size) in weights.0.iter_mut().zip(gradient).zip(size) {
+            // features that are always 0 can't be fitted, so their weights stay as they are
+            if size > 0.0 {
+                *weight -= rate * gradient / size;
+            }
+        }
+    }
+    weights
+}
+
+// the evaluation `Weights::evaluate` would give a position with these features
+fn predict(features: &[f64; FEATURE_COUNT], weights: Weights) -> f64 {
+    EVAL_LIMIT * dot(features, weights).tanh()
+}
+
+fn dot(features: &[f64; FEATURE_COUNT], weights: Weights) -> f64 {
+    features.iter().zip(weights.0).map(|(f, w)| f * w).sum()
+}
+
+// in the format `Weights::parse` reads, one feature per line
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, weight) in FEATURES.iter().zip(self.0) {
+            writeln!(f, "{} {}", name, weight)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordering::sample_positions;
+    use crate::{ClassicalBoardState, Move};
+
+    #[test]
+    fn features_of_positions() {
+        // nothing on the board but X having the move
+        let b = BoardState::new();
+        assert_eq!(features(&b), [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        let score = evaluate(&b);
+        assert_eq!(score.terms.iter().sum::<f64>(), Weights::default().0[6]);
+
+        // X has 0 and 1, O has the center, then O's mark 3 went in 2 and 6 and X's mark 4 in 6 and 8
+        let mut c = ClassicalBoardState::new();
+        c.set_x(0);
+        c.set_x(1);
+        c.set_o(4);
+        let b: BoardState = BoardState::from_marks(c, &[(3, 2, 6), (4, 6, 8)], 5);
+        let [open, progress, marks, threats, biggest, choice, tempo] = features(&b);
+        // X can still use the top and bottom rows and the left and right columns, and O the middle
+        // and bottom rows, the right column and the diagonal through 2, 4 and 6
+        assert_eq!(open, 4.0 - 4.0);
+        assert_eq!(progress, (4.0 + 1.0) - (1.0 + 1.0));
+        // X's mark is in the bottom row twice and the two columns once, O's is in the bottom row,
+        // the right column and twice in the diagonal
+        assert_eq!(marks, 4.0 - 4.0);
+        // 2, 6 and 8 are all entangled, and it's O's move
+        assert_eq!((threats, biggest, choice, tempo), (-3.0, -3.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn values_stay_inside_wins() {
+        let heavy = Weights([100.0; FEATURE_COUNT]);
+        for b in sample_positions() {
+            let score = heavy.evaluate(&b);
+            assert!(score.value.abs() <= EVAL_LIMIT);
+            assert!(evaluate(&b).value.abs() < 1.0);
+        }
+        // a cycle for X to collapse is good for X
+        let mut b = BoardState::new();
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(0, 1));
+        assert_eq!(features(&b)[5], 1.0);
+        assert!(evaluate(&b).value > 0.0);
+    }
+
+    #[test]
+    fn tuning_fits_results() {
+        // results that only depend on tempo and line progress, with some noise from ties
+        let samples: Vec<Sample> = (0..200)
+            .map(|i| {
+                let mut features = [0.0; FEATURE_COUNT];
+                features[1] = f64::from(i % 7) - 3.0;
+                features[6] = if i % 2 == 0 { 1.0 } else { -1.0 };
+                let result = match 0.4 * features[1] - 0.3 * features[6] {
+                    _ if i % 10 == 0 => 0.0,
+                    lean if lean > 0.0 => 1.0,
+                    _ => -1.0,
+                };
+                (features, result)
+            })
+            .collect();
+        let start = Weights::default();
+        let tuned = tune(&samples, start, 500, 0.1);
+        assert!(prediction_error(&samples, tuned) < prediction_error(&samples, start) / 2.0);
+        assert!(tuned.0[1] > 0.0 && tuned.0[6] < 0.0);
+        // features that never came up keep their weights
+        assert_eq!(tuned.0[0], start.0[0]);
+    }
+
+    #[test]
+    fn weights_files() {
+        let shipped = include_str!("../weights/default.txt");
+        assert_eq!(Weights::parse(shipped).unwrap(), Weights::default());
+        let weights = Weights::parse("tempo -0.5 # O is better off\n\nopen_lines 2").unwrap();
+        assert_eq!(weights.0[6], -0.5);
+        assert_eq!(weights.0[0], 2.0);
+        assert_eq!(weights.0[1], Weights::default().0[1]);
+        assert_eq!(Weights::parse(&weights.to_string()).unwrap(), weights);
+        assert!(matches!(
+            Weights::parse("tempo 1\ntempi 1"),
+            Err(FileError::Parse { line: 2, .. })
+        ));
+        assert!(Weights::parse("tempo").is_err());
+        assert!(Weights::parse("tempo NaN").is_err());
+    }
+}
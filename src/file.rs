@@ -0,0 +1,167 @@
+use crate::RuleSet;
+use std::{error, fmt, fs, io, path::Path, slice::Chunks};
+
+/// How one of the binary files things are saved in is laid out
+///
+/// Every file starts with a 4 byte magic number and a version byte, then the rest of a header
+/// that's the same size in every file of its kind, then records that are all the same size.
+/// Files with rules in them have them just after the version, like `RuleSet::to_bytes`, and files
+/// with a number of records have it as the last 4 little-endian bytes of the header.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Layout {
+    // what the file holds, for error messages
+    pub kind: &'static str,
+    pub magic: &'static [u8; 4],
+    pub version: u8,
+    // the bytes before the records, counting the magic number and version
+    pub header: usize,
+    // the bytes in each record
+    pub record: usize,
+}
+
+impl Layout {
+    // a file with just the magic number and version in it so far, with room for `count` records
+    pub fn start(&self, count: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.header + self.record * count);
+        bytes.extend_from_slice(self.magic);
+        bytes.push(self.version);
+        bytes
+    }
+
+    // the error for a file of this kind with something wrong in it
+    pub fn format(&self, message: &str) -> FileError {
+        FileError::Format {
+            kind: self.kind,
+            message: message.to_string(),
+        }
+    }
+
+    // checks the magic number and version, and that the whole header is there
+    pub fn check(&self, bytes: &[u8]) -> Result<(), FileError> {
+        if bytes.len() < self.header || &bytes[..4] != self.magic {
+            return Err(self.format("not the right kind of file"));
+        }
+        if bytes[4] != self.version {
+            return Err(self.format("made by a different version"));
+        }
+        Ok(())
+    }
+
+    // the rules just after the version, once the file's been `check`ed
+    pub fn rules(&self, bytes: &[u8]) -> Result<RuleSet, FileError> {
+        RuleSet::from_bytes([bytes[5], bytes[6], bytes[7], bytes[8]])
+            .map_err(|message| self.format(message))
+    }
+
+    // the number of records at the end of the header, once the file's been `check`ed
+    pub fn count(&self, bytes: &[u8]) -> usize {
+        let at = self.header - 4;
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize
+    }
+
+    // the records after the header, as long as there are exactly `count` of them
+    pub fn records<'a>(&self, bytes: &'a [u8], count: usize) -> Result<Chunks<'a, u8>, FileError> {
+        if bytes.len() != self.header + self.record * count {
+            return Err(self.format("the file is the wrong length"));
+        }
+        Ok(bytes[self.header..].chunks(self.record))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, bytes: Vec<u8>) -> Result<(), FileError> {
+        fs::write(path, bytes).map_err(|e| FileError::io(self.kind, e))
+    }
+
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<Vec<u8>, FileError> {
+        fs::read(path).map_err(|e| FileError::io(self.kind, e))
+    }
+}
+
+/// Why a saved file couldn't be read or written
+#[derive(Debug)]
+pub enum FileError {
+    Io {
+        kind: &'static str,
+        error: io::Error,
+    },
+    // the file was read, but isn't a good file of its kind
+    Format {
+        kind: &'static str,
+        message: String,
+    },
+    // a text file has something on this line that can't be read
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl FileError {
+    pub(crate) fn io(kind: &'static str, error: io::Error) -> Self {
+        FileError::Io { kind, error }
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io { kind, error } => {
+                write!(f, "couldn't read or write the {}: {}", kind, error)
+            }
+            FileError::Format { kind, message } => write!(f, "bad {} file: {}", kind, message),
+            FileError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for FileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: Layout = Layout {
+        kind: "test",
+        magic: b"QTXX",
+        version: 3,
+        header: 13,
+        record: 2,
+    };
+
+    fn file(records: &[[u8; 2]]) -> Vec<u8> {
+        let mut bytes = LAYOUT.start(records.len());
+        bytes.extend_from_slice(&RuleSet::default().to_bytes());
+        bytes.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        for record in records {
+            bytes.extend_from_slice(record);
+        }
+        bytes
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let bytes = file(&[[1, 2], [3, 4]]);
+        assert!(LAYOUT.check(&bytes).is_ok());
+        assert_eq!(LAYOUT.rules(&bytes).unwrap(), RuleSet::default());
+        assert_eq!(LAYOUT.count(&bytes), 2);
+        let records: Vec<&[u8]> = LAYOUT.records(&bytes, 2).unwrap().collect();
+        assert_eq!(records, [&[1, 2][..], &[3, 4][..]]);
+    }
+
+    #[test]
+    fn bad_files() {
+        let bytes = file(&[[1, 2]]);
+        // too short for the header
+        assert!(LAYOUT.check(&bytes[..LAYOUT.header - 1]).is_err());
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(LAYOUT.check(&bad).is_err());
+        let mut bad = bytes.clone();
+        bad[4] += 1;
+        assert!(LAYOUT.check(&bad).is_err());
+        // a record missing, or cut short
+        assert!(LAYOUT.records(&bytes, 2).is_err());
+        assert!(LAYOUT.records(&bytes[..bytes.len() - 1], 1).is_err());
+        let message = LAYOUT.records(&bytes, 2).unwrap_err().to_string();
+        assert_eq!(message, "bad test file: the file is the wrong length");
+    }
+}
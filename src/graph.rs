@@ -1,14 +1,16 @@
-/// Graph with implicit nodes 0-8 for a tic tac toe board
-#[derive(Clone)]
-pub struct BoardGraph {
-    edges: [[u8; 9]; 9],
+/// Graph with implicit nodes 0-(N-1) for a tic tac toe board, 0-8 by default
+#[derive(Clone, PartialEq, Eq)]
+pub struct BoardGraph<const N: usize = 9> {
+    edges: [[u8; N]; N],
 }
 
 impl BoardGraph {
     pub fn new() -> Self {
-        Self { edges: [[0; 9]; 9] }
+        Self::default()
     }
+}
 
+impl<const N: usize> BoardGraph<N> {
     pub fn add_edge(&mut self, u: u8, v: u8) {
         debug_assert!(u != v);
         self.edges[u as usize][v as usize] += 1;
@@ -21,6 +23,13 @@ impl BoardGraph {
         self.edges[v as usize][u as usize] -= 1;
     }
 
+    // sets the number of edges between two vertices, used for rebuilding the graph when undoing moves
+    pub fn set_edge_count(&mut self, u: u8, v: u8, count: u8) {
+        debug_assert!(u != v);
+        self.edges[u as usize][v as usize] = count;
+        self.edges[v as usize][u as usize] = count;
+    }
+
     // finds the cycle in the graph if there is one and stores it in the given vector
     pub fn has_cycle(&self, start: u8, store: &mut smallvec::SmallVec<[u8; 9]>) -> bool {
         store.clear();
@@ -38,11 +47,11 @@ impl BoardGraph {
         }
 
         // case 2: cycle of length >2 with bfs
-        fn bfs(
+        fn bfs<const N: usize>(
             start: u8,
             parent: Option<u8>,
-            visited: &mut [bool; 9],
-            graph: &[[u8; 9]; 9],
+            visited: &mut [bool],
+            graph: &[[u8; N]],
             store: &mut smallvec::SmallVec<[u8; 9]>,
         ) -> bool {
             if visited[start as usize] {
@@ -53,7 +62,7 @@ impl BoardGraph {
             visited[start as usize] = true;
             let parent_idx = match parent {
                 Some(p) => p as usize,
-                None => std::usize::MAX,
+                None => usize::MAX,
             };
             for v in graph[start as usize]
                 .iter()
@@ -77,53 +86,69 @@ impl BoardGraph {
             false
         }
 
-        let mut visited = [false; 9];
+        let mut visited = [false; N];
         bfs(start, None, &mut visited, &self.edges, store);
         !store.is_empty()
     }
 
     // clears all edges with the vertex
     pub fn clear_vert(&mut self, v: u8) {
-        self.edges[v as usize] = [0; 9];
+        self.edges[v as usize] = [0; N];
         for arr in self.edges.iter_mut() {
             arr[v as usize] = 0;
         }
     }
 
-    pub fn edges(&self) -> &[[u8; 9]; 9] {
+    pub fn edges(&self) -> &[[u8; N]; N] {
         &self.edges
     }
+
+    // the groups of vertices joined to each other by edges, as masks with a bit for each vertex
+    // vertices without any edges aren't in a group
+    pub fn components(&self) -> Vec<u32> {
+        let mut components = Vec::new();
+        let mut seen = 0u32;
+        for start in 0..N {
+            if seen & 1 << start > 0 || self.edges[start].iter().all(|&count| count == 0) {
+                continue;
+            }
+            let mut component = 1u32 << start;
+            let mut stack = vec![start];
+            while let Some(v) = stack.pop() {
+                for (u, &count) in self.edges[v].iter().enumerate() {
+                    if count > 0 && component & 1 << u == 0 {
+                        component |= 1 << u;
+                        stack.push(u);
+                    }
+                }
+            }
+            seen |= component;
+            components.push(component);
+        }
+        components
+    }
+}
+
+// graphs for other board sizes are made with `BoardGraph::<N>::default()`
+impl<const N: usize> Default for BoardGraph<N> {
+    fn default() -> Self {
+        Self { edges: [[0; N]; N] }
+    }
 }
 
 // more debugging stuff you can ignore
 use std::fmt;
-impl fmt::Debug for BoardGraph {
+impl<const N: usize> fmt::Debug for BoardGraph<N> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "BoardGraph {{
-\t   0  1  2  3  4  5  6  7  8
-\t0 {:?}
-\t1 {:?}
-\t2 {:?}
-\t3 {:?}
-\t4 {:?}
-\t5 {:?}
-\t6 {:?}
-\t7 {:?}
-\t8 {:?}
-}}
-",
-            self.edges[0],
-            self.edges[1],
-            self.edges[2],
-            self.edges[3],
-            self.edges[4],
-            self.edges[5],
-            self.edges[6],
-            self.edges[7],
-            self.edges[8]
-        )
+        write!(f, "BoardGraph {{\n\t ")?;
+        for v in 0..N {
+            write!(f, "{:>3}", v)?;
+        }
+        writeln!(f)?;
+        for (v, row) in self.edges.iter().enumerate() {
+            writeln!(f, "\t{} {:?}", v, row)?;
+        }
+        writeln!(f, "}}")
     }
 }
 
@@ -187,6 +212,19 @@ mod tests {
         assert!(!b.has_cycle(0, &mut store));
     }
 
+    #[test]
+    fn components() {
+        let mut b = BoardGraph::new();
+        assert!(b.components().is_empty());
+        b.add_edge(0, 4);
+        b.add_edge(4, 8);
+        b.add_edge(2, 6);
+        b.add_edge(6, 2);
+        assert_eq!(b.components(), vec![0b1_0001_0001, 0b0_0100_0100]);
+        b.add_edge(8, 2);
+        assert_eq!(b.components(), vec![0b1_0101_0101]);
+    }
+
     #[bench]
     fn bench_cyclic(bench: &mut Bencher) {
         let mut b = BoardGraph::new();
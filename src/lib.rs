@@ -8,156 +8,205 @@ extern crate test;
 
 // declares the module graph, which is defined in graph.rs
 pub mod graph;
+// propagates collapses through entangled squares, defined in collapse.rs
+pub mod collapse;
+// enumerating the classical boards the quantum moves could still become, defined in worlds.rs
+pub mod worlds;
+// the shape of the board and its winning lines, defined in topology.rs
+pub mod topology;
+// drawing boards of any shape as plain text, defined in text.rs
+pub mod text;
+// the optional rules different versions of the game use, defined in rules.rs
+pub mod rules;
+// quantum boards played inside the squares of a bigger board, defined in ultimate.rs
+pub mod ultimate;
+// looking ahead for the best move, defined in search.rs
+pub mod search;
+// deciding which moves the search should try first, defined in ordering.rs
+pub mod ordering;
+// remembering searched positions, shared between threads, defined in table.rs
+pub mod table;
+// searching on every core at once, defined in parallel.rs
+pub mod parallel;
+// the layout of the binary files things are saved in, and their errors, defined in file.rs
+pub mod file;
+// exact results for positions near the end of the game, defined in tablebase.rs
+pub mod tablebase;
+// scored moves for the start of the game, defined in book.rs
+pub mod book;
+// how good a position looks without searching it, defined in eval.rs
+pub mod eval;
+// the computer playing games against itself, defined in selfplay.rs
+pub mod selfplay;
+// a small neural network for evaluating positions, defined in network.rs
+pub mod network;
+// a player that learns the value of positions from games against itself, defined in td.rs
+pub mod td;
+// proving forced wins with proof-number search, defined in proof.rs
+pub mod proof;
 
 // declares a Copy struct (implicitly copied when passed as an argument, like an integer)
-// with one member, called `self.0` which has type u32
+// with two members: `bits`, which has type u64, and `topo`, a reference to the shape of the board
 // integer types are denoted by their sign (u for unsigned, i for signed) and the number of bits
-// so u32 is a 32-bit unsigned integer
+// so u64 is a 64-bit unsigned integer
 // usize and isize are signed and unsigned integers of the width of a pointer. On 64 bit architectures its a 64 bit integer
 // usize is used for array and vector indices, and casts are done with the `as` operator.
+// the `'static` means the topology lives for the whole program, see topology.rs
 #[derive(Copy, Clone)]
-pub struct ClassicalBoardState(u32);
+pub struct ClassicalBoardState {
+    bits: u64,
+    topo: &'static Topology,
+}
+
+// `Topology::leak` only ever makes one of each topology, so two boards have the same shape exactly
+// when they point at the same one, which is much quicker than comparing every line
+impl PartialEq for ClassicalBoardState {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits == other.bits && std::ptr::eq(self.topo, other.topo)
+    }
+}
+
+impl Eq for ClassicalBoardState {}
 
 // defines methods (or static functions) on the ClassicalBoardState struct
 impl ClassicalBoardState {
-    // defines a static method like `ClassicalBoardState::new()` that returns an instance with the member having the value 0
+    // defines a static method like `ClassicalBoardState::new()` that returns an empty 3x3 board
     // this is a PUBlic FuNction, hence `pub fn`
     pub fn new() -> Self {
-        Self(0)
+        Self::with_topology(Topology::classic())
     }
 
-    // another static method, taking an unsigned byte and returning a 32-bit unsigned integer
+    pub fn with_topology(topo: &'static Topology) -> Self {
+        Self { bits: 0, topo }
+    }
+
+    // another static method, taking an unsigned byte and returning a 64-bit unsigned integer
     // computes a bit mask used for efficient storage
-    const fn x_mask(sq: u8) -> u32 {
+    // each square has two bits, holding 0 if it's empty or one more than the player whose mark is there
+    const fn x_mask(sq: u8) -> u64 {
         1 << (2 * sq)
     }
 
-    const fn o_mask(sq: u8) -> u32 {
+    const fn o_mask(sq: u8) -> u64 {
         1 << (2 * sq + 1)
     }
 
+    // the low bit of every square
+    const LOW_BITS: u64 = 0x5555_5555_5555_5555;
+
+    pub fn topology(&self) -> &'static Topology {
+        self.topo
+    }
+
     // this is a method, invokable like
-    // `let classical_board = ClassicalBoardState::new(); classical_board.set_x(2);`
+    // `let classical_board = ClassicalBoardState::new(); classical_board.set(2, 0);`
     // the first argument is `self`, which makes it a method like in Python
     // Also, the method takes a mutable reference (`&mut`) which does not consume the instance but can mutate it.
     // sq is short for square, ab abbreviation used throughout
+    // `player` is 0 for X, 1 for O and 2 for the third player
+    pub fn set(&mut self, sq: u8, player: u8) {
+        debug_assert!((player as usize) < MAX_PLAYERS);
+        self.clear(sq);
+        self.bits |= (player as u64 + 1) << (2 * sq);
+    }
+
     pub fn set_x(&mut self, sq: u8) {
-        self.0 |= Self::x_mask(sq);
-        self.0 &= !Self::o_mask(sq);
+        self.set(sq, 0);
     }
 
     pub fn set_o(&mut self, sq: u8) {
-        self.0 |= Self::o_mask(sq);
-        self.0 &= !Self::x_mask(sq);
+        self.set(sq, 1);
+    }
+
+    // empties the square again, used when taking back a move
+    pub fn clear(&mut self, sq: u8) {
+        self.bits &= !(Self::x_mask(sq) | Self::o_mask(sq));
     }
 
     // This is another method, but this one takes a immutable reference (&self), so it cannot mutate the instance it is called on.
     // in Rust, the last value in a scope is implicitly returned, hence no semicolon at the end and no `return` keyword
+    // the player whose mark is in the square, `None` if it's empty
+    pub fn player_at(&self, sq: u8) -> Option<u8> {
+        ((self.bits >> (2 * sq)) as u8 & 0b11).checked_sub(1)
+    }
+
     pub fn is_x(&self, sq: u8) -> bool {
-        self.0 & Self::x_mask(sq) > 0
+        self.player_at(sq) == Some(0)
     }
 
     pub fn is_o(&self, sq: u8) -> bool {
-        self.0 & Self::o_mask(sq) > 0
+        self.player_at(sq) == Some(1)
     }
 
     pub fn is_empty(&self, sq: u8) -> bool {
-        !(self.is_o(sq) || self.is_x(sq))
+        self.bits & (Self::x_mask(sq) | Self::o_mask(sq)) == 0
     }
 
-    pub fn x_wins(&self) -> bool {
-        // contains all the masks in which x could win, there's only 8!
-        // the type of this variable is [u32; 8], which is an array of u32  with length 8
-        const X_WINS_MASKS: [u32; 8] = [
-            // horizontals
-            ClassicalBoardState::x_mask(0)
-                | ClassicalBoardState::x_mask(1)
-                | ClassicalBoardState::x_mask(2),
-            ClassicalBoardState::x_mask(3)
-                | ClassicalBoardState::x_mask(4)
-                | ClassicalBoardState::x_mask(5),
-            ClassicalBoardState::x_mask(6)
-                | ClassicalBoardState::x_mask(7)
-                | ClassicalBoardState::x_mask(8),
-            // verticals
-            ClassicalBoardState::x_mask(0)
-                | ClassicalBoardState::x_mask(3)
-                | ClassicalBoardState::x_mask(6),
-            ClassicalBoardState::x_mask(1)
-                | ClassicalBoardState::x_mask(4)
-                | ClassicalBoardState::x_mask(7),
-            ClassicalBoardState::x_mask(2)
-                | ClassicalBoardState::x_mask(5)
-                | ClassicalBoardState::x_mask(8),
-            // diagonals
-            ClassicalBoardState::x_mask(0)
-                | ClassicalBoardState::x_mask(4)
-                | ClassicalBoardState::x_mask(8),
-            ClassicalBoardState::x_mask(2)
-                | ClassicalBoardState::x_mask(4)
-                | ClassicalBoardState::x_mask(6),
-        ];
-        // `let` declares a variable, which is immutable by default. Adding `mut` makes the variable mutable.
-        let mut wins = false;
-        // faster to branch or not?
+    // the low bit of each of `player`'s squares, so they line up with the topology's lines
+    fn marks_of(&self, player: u8) -> u64 {
+        let low = self.bits & Self::LOW_BITS;
+        let high = (self.bits >> 1) & Self::LOW_BITS;
+        match player {
+            0 => low & !high,
+            1 => high & !low,
+            _ => low & high,
+        }
+    }
+
+    // a bit for each of `player`'s squares, lined up with `Topology::lines` instead of the two bits per square
+    pub fn squares_of(&self, player: u8) -> u32 {
+        let marks = self.marks_of(player);
+        (0..self.topo.cells())
+            .filter(|&sq| marks & Self::x_mask(sq) > 0)
+            .fold(0, |squares, sq| squares | 1 << sq)
+    }
+
+    // the low bit of every square that has a mark
+    fn filled(&self) -> u64 {
+        (self.bits | self.bits >> 1) & Self::LOW_BITS
+    }
+
+    pub fn wins(&self, player: u8) -> bool {
+        // the topology has a mask for each way to win, there's only 8 on the normal board!
         // `.iter()` takes an iterable container, like an array, and returns an iterator that yields a reference to each item in the container
         // the `&mask` pattern-matches / destructures the reference, so that `mask` contains the actual value.
-        for &mask in X_WINS_MASKS.iter() {
-            wins = wins || (self.0 & mask == mask)
-        }
-        wins
+        let marks = self.marks_of(player);
+        self.topo.x_lines().iter().any(|&mask| mask & !marks == 0)
+    }
+
+    pub fn x_wins(&self) -> bool {
+        self.wins(0)
     }
 
     pub fn o_wins(&self) -> bool {
-        // contains all the masks in which o could win, there's only 8!
-        // the type of this variable is [u32; 8], which is an array of u32  with length 8
-        const X_WINS_MASKS: [u32; 8] = [
-            // horizontals
-            ClassicalBoardState::o_mask(0)
-                | ClassicalBoardState::o_mask(1)
-                | ClassicalBoardState::o_mask(2),
-            ClassicalBoardState::o_mask(3)
-                | ClassicalBoardState::o_mask(4)
-                | ClassicalBoardState::o_mask(5),
-            ClassicalBoardState::o_mask(6)
-                | ClassicalBoardState::o_mask(7)
-                | ClassicalBoardState::o_mask(8),
-            // verticals
-            ClassicalBoardState::o_mask(0)
-                | ClassicalBoardState::o_mask(3)
-                | ClassicalBoardState::o_mask(6),
-            ClassicalBoardState::o_mask(1)
-                | ClassicalBoardState::o_mask(4)
-                | ClassicalBoardState::o_mask(7),
-            ClassicalBoardState::o_mask(2)
-                | ClassicalBoardState::o_mask(5)
-                | ClassicalBoardState::o_mask(8),
-            // diagonals
-            ClassicalBoardState::o_mask(0)
-                | ClassicalBoardState::o_mask(4)
-                | ClassicalBoardState::o_mask(8),
-            ClassicalBoardState::o_mask(2)
-                | ClassicalBoardState::o_mask(4)
-                | ClassicalBoardState::o_mask(6),
-        ];
-        let mut wins = false;
-        // TODO faster to branch or not?
-        for &mask in X_WINS_MASKS.iter() {
-            wins = wins || (self.0 & mask == mask)
-        }
-        wins
+        self.wins(1)
     }
 
     fn has_winner(&self) -> bool {
-        self.x_wins() || self.o_wins()
+        (0..MAX_PLAYERS as u8).any(|player| self.wins(player))
     }
 
     pub fn game_is_over(&self) -> bool {
         if self.has_winner() {
             return true;
         }
-        // the game is over if 8 or more squares are filled, as quantum moves cannot be in a single square
-        return self.0.count_ones() >= 8;
+        // the game is over once every square is filled
+        // the last square can't take a quantum move, so it's filled with a `Move::Classical`
+        self.filled().count_ones() >= self.topo.cells() as u32
+    }
+
+    // the only empty square, if all the others are filled
+    pub fn last_square(&self) -> Option<u8> {
+        if self.filled().count_ones() + 1 != self.topo.cells() as u32 {
+            return None;
+        }
+        (0..self.topo.cells()).find(|&sq| self.is_empty(sq))
+    }
+}
+
+impl Default for ClassicalBoardState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -166,107 +215,108 @@ impl ClassicalBoardState {
 use std::fmt;
 impl fmt::Debug for ClassicalBoardState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let get = |sq| {
-            if self.is_x(sq) {
-                "x"
-            } else if self.is_o(sq) {
-                "o"
-            } else {
-                " "
-            }
+        let get = |sq| match self.player_at(sq) {
+            Some(player) => MARKS[player as usize].to_ascii_lowercase().to_string(),
+            None => " ".to_string(),
         };
-        write!(
-            f,
-            "ClassicalBoardState {{
-\t{a0}|{a1}|{a2}
-\t-----
-\t{a3}|{a4}|{a5}
-\t-----
-\t{a6}|{a7}|{a8}
-}}",
-            a0 = get(0),
-            a1 = get(1),
-            a2 = get(2),
-            a3 = get(3),
-            a4 = get(4),
-            a5 = get(5),
-            a6 = get(6),
-            a7 = get(7),
-            a8 = get(8)
-        )
+        let width = self.topo.width();
+        let layer_size = width * self.topo.height();
+        writeln!(f, "ClassicalBoardState {{")?;
+        for layer in 0..self.topo.depth() {
+            if layer > 0 {
+                writeln!(f)?;
+            }
+            for row in 0..self.topo.height() {
+                if row > 0 {
+                    writeln!(f, "\t{}", "-".repeat(2 * width as usize - 1))?;
+                }
+                let cells: Vec<_> = (0..width)
+                    .map(|col| get(layer * layer_size + row * width + col))
+                    .collect();
+                writeln!(f, "\t{}", cells.join("|"))?;
+            }
+        }
+        write!(f, "}}")
     }
 }
 
-// defines another struct with one member of type [u16; 9], an array of u16 with length 9
-#[derive(Clone)]
-pub struct QuantumBoardState([u16; 9]);
+// defines another struct with one member of type [u32; N], an array of u32 with length N
+// N is a const generic, so `QuantumBoardState<9>` has an array of length 9
+// each u32 has a bit for each move that has a mark in that square
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct QuantumBoardState<const N: usize = 9>([u32; N]);
 
 impl QuantumBoardState {
     pub fn new() -> Self {
-        Self([0; 9])
+        Self::default()
     }
 
-    fn mask(mov: u8) -> u16 {
+    // the same for every board size, so it lives here to be called as `QuantumBoardState::mask`
+    fn mask(mov: u8) -> u32 {
+        debug_assert!(mov < Self::MAX_MOVES);
         1 << mov
     }
 
+    // there's a bit for each move number, so a game can't use more than this many
+    pub const MAX_MOVES: u8 = 32;
+}
+
+impl<const N: usize> QuantumBoardState<N> {
     pub fn is(&self, mov: u8, sq: u8) -> bool {
-        self.0[sq as usize] & Self::mask(mov) > 0
+        self.0[sq as usize] & QuantumBoardState::mask(mov) > 0
     }
 
     pub fn add(&mut self, mov: u8, sq: u8, sq2: u8) {
-        self.0[sq as usize] |= Self::mask(mov);
-        self.0[sq2 as usize] |= Self::mask(mov);
+        self.0[sq as usize] |= QuantumBoardState::mask(mov);
+        self.0[sq2 as usize] |= QuantumBoardState::mask(mov);
+    }
+
+    // the opposite of `add`, used when taking back a move
+    pub fn remove(&mut self, mov: u8, sq: u8, sq2: u8) {
+        self.0[sq as usize] &= !QuantumBoardState::mask(mov);
+        self.0[sq2 as usize] &= !QuantumBoardState::mask(mov);
     }
 
     pub fn clear(&mut self, sq: u8) {
         self.0[sq as usize] = 0
     }
 
-    pub fn mask_in(&self, sq: u8) -> u16 {
+    pub fn mask_in(&self, sq: u8) -> u32 {
         self.0[sq as usize]
     }
 
     pub fn is_sound(&self) -> bool {
         // for each move, there should be either two or zero instances
-        for mov in 0..9 {
+        // measurements and skipped turns use up move numbers without adding marks, so a mark's number
+        // can be anything a mask has room for, not just below the number of squares
+        for mov in 0..QuantumBoardState::MAX_MOVES {
             let mut count = 0;
-            for sq in 0..9 {
+            for sq in 0..N as u8 {
                 count += if self.is(mov, sq) { 1 } else { 0 };
             }
             if !(count == 2 || count == 0) {
                 return false;
             }
         }
-        for sq in 0..9 {
-            if self.0[sq as usize] > 0b111111111 {
-                return false;
-            }
-        }
         true
     }
 }
 
+// boards with room for other numbers of squares are made with `QuantumBoardState::<N>::default()`
+impl<const N: usize> Default for QuantumBoardState<N> {
+    fn default() -> Self {
+        Self([0; N])
+    }
+}
+
 // again, only used for debugging
-impl fmt::Debug for QuantumBoardState {
+impl<const N: usize> fmt::Debug for QuantumBoardState<N> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "QuantumBoardState {{
-\t0:{:016b} 1:{:016b} 2:{:016b},
-\t3:{:016b} 4:{:016b} 5:{:016b},
-\t6:{:016b} 7:{:016b} 8:{:016b},
-}}",
-            self.0[0],
-            self.0[1],
-            self.0[2],
-            self.0[3],
-            self.0[4],
-            self.0[5],
-            self.0[6],
-            self.0[7],
-            self.0[8]
-        )
+        writeln!(f, "QuantumBoardState {{")?;
+        for (sq, mask) in self.0.iter().enumerate() {
+            writeln!(f, "\t{}:{:032b},", sq, mask)?;
+        }
+        write!(f, "}}")
     }
 }
 
@@ -324,47 +374,141 @@ mod hidden_board_test {
 // in this case, the `Quantum` type represents a normal move that occurs in two places at once
 // the locations are represented as a tuple of 2 u8
 // the Collapse discriminant has two named fields, the square we collapse first and the move we set it to
-#[derive(Copy, Clone, Debug)]
+// a Classical move puts a mark straight into a square, which is only allowed when it's the last one left
+// a Measure move spends a turn to collapse a square and everything its marks force, if the rules allow it
+// under `Measuring::Random` it doesn't say which mark is found, so it's played with `measure_randomly` or
+// `make_measure` instead of `do_move`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Move {
     Quantum(u8, u8),
     Collapse { sq: u8, mov: u8 },
+    Classical(u8),
+    Measure(u8),
 }
 
 // import the BoardGraph struct from graph.rs
 use self::graph::BoardGraph;
+use rand::Rng;
+use smallvec::SmallVec;
+
+// re-export the types other modules hand out so users can write `qtictac_ai::CollapseTrace`
+pub use self::collapse::{Assignment, CollapseError, CollapseTrace};
+pub use self::rules::{CollapseChooser, Measuring, Outcome, RuleSet, MARKS, MAX_PLAYERS};
+pub use self::topology::Topology;
+pub use self::worlds::{WorldStats, Worlds};
 
+// everything `BoardState::unmake_move` needs to take back a move made with `BoardState::make_move`
+// this lets the AI search by mutating one board instead of cloning it for every child
 #[derive(Clone, Debug)]
-pub struct BoardState {
-    c: ClassicalBoardState, // tracks classical moves for win detection, etc.
-    q: QuantumBoardState,   // tracks quantum moves not yet collapsed
-    g: BoardGraph, // tracks the dependencies between squares based on quantum moves for cycle detections
-    next_mov: u8,  // tracks who is to move next and what number move it is
-    cycle: smallvec::SmallVec<[u8; 9]>, // A `SmallVec` is like an ArrayList in java
-                   // the "small" part is because the array is stack allocated if its small enough
-                   // in this case its a SmallVec of u8 that's stack allocated for up to 9 items
-                   // this variable contains all the squares in a cycle if there is one
+pub enum UndoInfo<const N: usize = 9> {
+    // a quantum move only adds two marks and an edge, so the squares are enough to remove them
+    Quantum(u8, u8),
+    // the last square just needs emptying again
+    Classical(u8),
+    // a collapse clears lots of squares, so the classical and quantum boards are saved whole
+    // they're Copy and small, and the graph can be rebuilt from the quantum marks
+    Collapse {
+        c: ClassicalBoardState,
+        q: QuantumBoardState<N>,
+        cycle: smallvec::SmallVec<[u8; 9]>,
+    },
+    // a measurement is the same, except there's never a cycle and it used up a turn
+    Measure {
+        c: ClassicalBoardState,
+        q: QuantumBoardState<N>,
+    },
+}
+
+// N is the number of squares the board has room for, 9 unless it says otherwise
+// the topology inside `c` says how many of them are actually used and how to win
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BoardState<const N: usize = 9> {
+    c: ClassicalBoardState,  // tracks classical moves for win detection, etc.
+    q: QuantumBoardState<N>, // tracks quantum moves not yet collapsed
+    g: BoardGraph<N>, // tracks the dependencies between squares based on quantum moves for cycle detections
+    next_mov: u8,     // tracks who is to move next and what number move it is
+    // A `SmallVec` is like an ArrayList in java
+    // the "small" part is because the array is stack allocated if its small enough
+    // in this case its a SmallVec of u8 that's stack allocated for up to 9 items
+    // this variable contains all the squares in a cycle if there is one
+    cycle: smallvec::SmallVec<[u8; 9]>,
+    rules: RuleSet, // which version of the rules the game is played by
 }
 
 impl BoardState {
+    // the normal 3x3 game
     pub fn new() -> Self {
+        Self::with_topology(Topology::classic())
+    }
+}
+
+impl<const N: usize> BoardState<N> {
+    // a game on any board with room for its cells, like `BoardState::<16>::with_topology(Topology::grid(4, 3).leak())`
+    // there can be more room than cells, which is how boards loaded from a file get played, and the
+    // squares past the last cell are never used
+    pub fn with_topology(topo: &'static Topology) -> Self {
+        assert!(topo.cells() as usize <= N && N <= Topology::MAX_CELLS);
         BoardState {
-            c: ClassicalBoardState::new(),
-            q: QuantumBoardState::new(),
-            g: BoardGraph::new(),
+            c: ClassicalBoardState::with_topology(topo),
+            q: QuantumBoardState::default(),
+            g: BoardGraph::default(),
             next_mov: 0,
             cycle: smallvec::SmallVec::new(),
+            rules: RuleSet::default(),
         }
     }
 
+    // a board partway through a game, built from its marks instead of played to
+    // `marks` are the quantum moves still in play as (move number, square, square), and the graph and
+    // cycle are worked out from them, so if there's a cycle the last of them has to be the move that closed it
+    pub(crate) fn from_marks(c: ClassicalBoardState, marks: &[(u8, u8, u8)], next_mov: u8) -> Self {
+        let mut b = Self::with_topology(c.topology());
+        b.c = c;
+        for &(mov, sq1, sq2) in marks.iter() {
+            b.q.add(mov, sq1, sq2);
+            b.g.add_edge(sq1, sq2);
+        }
+        b.next_mov = next_mov;
+        if let Some(&(_, sq1, _)) = marks.last() {
+            b.g.has_cycle(sq1, &mut b.cycle);
+        }
+        b
+    }
+
+    // plays the game by a different set of rules, like `BoardState::new().with_rules(rules)`
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        assert!(rules.players >= 2 && rules.players as usize <= MAX_PLAYERS);
+        self.rules = rules;
+        self
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn topology(&self) -> &'static Topology {
+        self.c.topology()
+    }
+
+    // the number of squares in play
+    pub fn cells(&self) -> u8 {
+        self.c.topology().cells()
+    }
+
     // this is what a getter looks like, it takes an immutable reference to self and returns an immutable reference to a member
     pub fn classic(&self) -> &ClassicalBoardState {
         &self.c
     }
 
-    pub fn quantum(&self) -> &QuantumBoardState {
+    pub fn quantum(&self) -> &QuantumBoardState<N> {
         &self.q
     }
 
+    // which squares share quantum marks, and how many
+    pub fn graph(&self) -> &BoardGraph<N> {
+        &self.g
+    }
+
     // unimportant plumbing, but for the curious:
     // the signuture says that this returns some type that implements the iterator trait (kind of like an interface)
     // that has the specialization that it iterates over `&u8`
@@ -378,7 +522,85 @@ impl BoardState {
     }
 
     pub fn has_cycle(&self) -> bool {
-        self.cycle.len() > 0
+        !self.cycle.is_empty()
+    }
+
+    // a number that stands for this position, for remembering positions that have been searched
+    // the graph and cycle follow from the marks, so only the boards and move number go into it
+    // different positions can share a key, but it's very unlikely
+    pub fn key(&self) -> u64 {
+        // the same mixing as the Fx hash the rust compiler uses, which is fast and good enough here
+        let mix =
+            |key: u64, word: u64| (key.rotate_left(5) ^ word).wrapping_mul(0x517c_c1b7_2722_0a95);
+        let key = mix(mix(0, self.c.bits), u64::from(self.next_mov));
+        self.q.0[..self.cells() as usize]
+            .iter()
+            .fold(key, |key, &marks| mix(key, u64::from(marks)))
+    }
+
+    // the key of this position turned whichever way of `symmetries` gives the lowest one, so
+    // positions that are turned or flipped versions of each other get the same key
+    // also gives the way it was turned, as the square each square turns into
+    pub fn canonical_key<'a>(&self, symmetries: &'a [Vec<u8>]) -> (u64, &'a [u8]) {
+        let cells = self.cells();
+        let marks: Vec<(u8, u8, u8)> = (0..self.next_mov)
+            .filter_map(|mov| {
+                let mut squares = (0..cells).filter(|&sq| self.q.is(mov, sq));
+                Some((mov, squares.next()?, squares.next()?))
+            })
+            .collect();
+        symmetries
+            .iter()
+            .map(|map| {
+                let mut c = self.c;
+                for sq in 0..cells {
+                    match self.c.player_at(sq) {
+                        Some(player) => c.set(map[sq as usize], player),
+                        None => c.clear(map[sq as usize]),
+                    }
+                }
+                let turned: Vec<_> = marks
+                    .iter()
+                    .map(|&(mov, sq1, sq2)| (mov, map[sq1 as usize], map[sq2 as usize]))
+                    .collect();
+                let key = Self::from_marks(c, &turned, self.next_mov).key();
+                (key, map.as_slice())
+            })
+            .min_by_key(|&(key, _)| key)
+            .expect("there's always the symmetry that leaves the board as it is")
+    }
+
+    // the player who has to pick how the cycle collapses, 0 for X and 1 for O
+    // `None` if there's no cycle, or if it collapses by measurement and nobody picks
+    pub fn collapse_chooser(&self) -> Option<u8> {
+        if self.cycle.is_empty() {
+            return None;
+        }
+        // the last quantum move closed the cycle
+        let creator = self.player_of(self.next_mov - 1);
+        match self.rules.collapse_chooser {
+            CollapseChooser::Opponent => Some(self.player_of(self.next_mov)),
+            CollapseChooser::Creator => Some(creator),
+            CollapseChooser::Measurement { .. } => None,
+        }
+    }
+
+    // who won under this board's rules, `None` if the game isn't over
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.rules.outcome(&self.c)
+    }
+
+    // the player who owns move number `mov`, 0 for X, 1 for O and 2 for a third player
+    pub fn player_of(&self, mov: u8) -> u8 {
+        self.rules.player_of(mov)
+    }
+
+    // the player whose turn it is
+    // this is the collapse chooser when there's a cycle, otherwise whoever owns the next quantum move
+    // a cycle that's measured is nobody's turn, so this is the player who moves after the measurement
+    pub fn to_move(&self) -> u8 {
+        self.collapse_chooser()
+            .unwrap_or_else(|| self.player_of(self.next_mov))
     }
 
     // mutates the board by doing the move given to the method
@@ -393,73 +615,316 @@ impl BoardState {
                 self.g.has_cycle(sq1, &mut self.cycle);
             }
             Move::Collapse { sq, mov } => {
-                // this branch can assume there is a cycle and that the square and move given are part of it
+                // only valid moves can be done, so there is a cycle and the square and move are part of it
+                // if that's somehow not true this panics rather than carry on as if the cycle had collapsed,
+                // `collapse` gives the error back instead
+                self.collapse(sq, mov)
+                    .expect("do_move is only given collapses of the cycle");
+            }
+            Move::Measure(sq) => {
+                // there's no rng here, so this can only play measurements that always find the oldest mark
+                // quietly finding the oldest one under random measuring would be a different game, so it panics
+                assert!(
+                    self.rules.measuring != Measuring::Random,
+                    "random measurements are played with `measure_randomly` or `make_measure`, not `do_move`"
+                );
+                let &(mov, _) = self
+                    .measure_chances(sq)
+                    .first()
+                    .expect("do_move is only given squares that can be measured");
+                self.measure(sq, mov)
+                    .expect("the chances only hold marks that are there");
+            }
+            Move::Classical(sq) => {
+                self.c.set(sq, self.player_of(self.next_mov));
+                self.next_mov += 1;
+            }
+        }
+    }
 
-                // find the index of sq in the cycle
-                let idx = self
-                    .cycle
-                    .iter()
-                    .enumerate() // enumerate takes an iterator and turns it into an iterator over a tuple: (index, value)
-                    .filter(|(_idx, &s)| s == sq) // the syntax here defines a lambda/closure (thats the `| |`), which destructures the
-                    // tuple from enumurate() into an index and the square
-                    .nth(0)
-                    .unwrap() // Rust has a special type, `Option`, which is an enum that makes a type nullable, basically.
-                    // unwrap() takes an Option and if its not null, returns the value. If it is null, the program crashes
-                    // here I know invariants are being upheld that allow me to unwrap the Option
-                    .0; // returns the first value from the tuple, the index
-
-                // last mask contains the moves that were in the last square but did not become classical
-                let last_mask = QuantumBoardState::mask(mov);
-
-                // destory the cycle, making the graph a tree so that we don't backtrack
-                // however, we need to ensure the the direction we collapse contains contains the other
-                // half the state we resolve to kick off the resolution in the dfs
-                let candidate_square = self.cycle[wrap(idx as isize + 1, self.cycle.len())];
-                if self.q.mask_in(candidate_square) & last_mask > 0 {
-                    self.g.clear_edge(sq, candidate_square);
-                } else {
-                    self.g
-                        .clear_edge(sq, self.cycle[wrap(idx as isize - 1, self.cycle.len())]);
-                }
-                resolve_depth_first(sq, last_mask, self);
-                self.cycle.clear(); // clear() empties the vector, but leaves the memory allocated for later reuse
-                fn resolve_depth_first(start: u8, last_mask: u16, board: &mut BoardState) {
-                    // resolve this one
-                    let decision_mask = board.q.mask_in(start) & last_mask;
-                    // in this case match functions exactly like switch
-                    match decision_mask {
-                        // 0th - 8th bit
-                        0b000000001 => board.c.set_x(start),
-                        0b000000010 => board.c.set_o(start),
-                        0b000000100 => board.c.set_x(start),
-                        0b000001000 => board.c.set_o(start),
-                        0b000010000 => board.c.set_x(start),
-                        0b000100000 => board.c.set_o(start),
-                        0b001000000 => board.c.set_x(start),
-                        0b010000000 => board.c.set_o(start),
-                        0b100000000 => board.c.set_x(start),
-                        // `_` is a catch all, like `default` in other languages. unreachable!() crashes the program
-                        _ => unreachable!(),
-                    };
-                    let next_last_mask = board.q.mask_in(start) & (!decision_mask);
-                    let edges = board.g.edges()[start as usize].clone();
-                    board.g.clear_vert(start);
-                    board.q.clear(start);
-                    // finds squares connected to this one in the graph
-                    for sq in edges
-                        .into_iter()
-                        .enumerate()
-                        .filter(|&(_idx, &amt)| amt > 0)
-                        .map(|(idx, _)| idx as u8)
-                    {
-                        resolve_depth_first(sq, next_last_mask, board);
-                    }
-                }
+    // skips a move number without putting down a mark, so the next move belongs to the next player
+    // used by ultimate boards, where turns go around all the boards together instead of within each one
+    pub(crate) fn pass(&mut self) {
+        debug_assert!(self.cycle.is_empty());
+        // the move after the pass needs a number too
+        debug_assert!(self.next_mov + 1 < QuantumBoardState::MAX_MOVES);
+        self.next_mov += 1;
+    }
+
+    // collapses the cycle by making `mov` classical in `sq`, like `do_move` does with `Move::Collapse`
+    // returns the order in which squares became classical, or an error with the board left untouched
+    pub fn collapse(&mut self, sq: u8, mov: u8) -> Result<CollapseTrace, CollapseError> {
+        // the dry run does all the checking, so applying it can't fail halfway through
+        let trace = self.collapse_trace(sq, mov)?;
+        self.apply(&trace);
+        self.cycle.clear(); // clear() empties the vector, but leaves the memory allocated for later reuse
+        Ok(trace)
+    }
+
+    // makes every square in `trace` classical
+    fn apply(&mut self, trace: &CollapseTrace) {
+        for a in trace.iter() {
+            self.c.set(a.sq, self.player_of(a.mov));
+            self.q.clear(a.sq);
+            self.g.clear_vert(a.sq);
+        }
+    }
+
+    // the two ways the cycle can collapse and how likely each one is, when the rules say it's measured
+    // `None` if there's no cycle or a player chooses the collapse
+    pub fn collapse_chances(&self) -> Option<[(Move, f64); 2]> {
+        let weight = match self.rules.collapse_chooser {
+            CollapseChooser::Measurement { weight } => weight.min(100),
+            _ => return None,
+        };
+        if self.cycle.is_empty() {
+            return None;
+        }
+        // the mark that closed the cycle has both its halves in the cycle, so either one can be made classical
+        let mov = self.next_mov - 1;
+        let mut squares = (0..self.cells()).filter(|&sq| self.q.is(mov, sq));
+        let low = squares.next()?;
+        let high = squares.next()?;
+        let p = f64::from(weight) / 100.0;
+        Some([
+            (Move::Collapse { sq: low, mov }, p),
+            (Move::Collapse { sq: high, mov }, 1.0 - p),
+        ])
+    }
+
+    // collapses the cycle the way a measurement would, picking between `collapse_chances` with `rng`
+    // pass a seeded rng, like `StdRng::seed_from_u64`, to get the same collapses every time
+    pub fn measure_cycle<R: Rng>(&mut self, rng: &mut R) -> Result<CollapseTrace, CollapseError> {
+        let [(first, p), (second, _)] = self.collapse_chances().ok_or(CollapseError::NoCycle)?;
+        let chosen = if rng.gen_bool(p) { first } else { second };
+        match chosen {
+            Move::Collapse { sq, mov } => self.collapse(sq, mov),
+            _ => unreachable!("collapse_chances only gives collapses"),
+        }
+    }
+
+    // the marks a measurement of `sq` could find and how likely each one is, oldest mark first
+    // empty if the rules don't allow measuring or `sq` can't be measured right now
+    pub fn measure_chances(&self, sq: u8) -> SmallVec<[(u8, f64); 9]> {
+        let mut chances = SmallVec::new();
+        if !self.is_valid(Move::Measure(sq)) {
+            return chances;
+        }
+        let marks = (0..self.next_mov).filter(|&mov| self.q.is(mov, sq));
+        match self.rules.measuring {
+            Measuring::Oldest => chances.extend(marks.take(1).map(|mov| (mov, 1.0))),
+            Measuring::Random => {
+                let p = 1.0 / self.q.mask_in(sq).count_ones() as f64;
+                chances.extend(marks.map(|mov| (mov, p)));
+            }
+            Measuring::Off => {}
+        }
+        chances
+    }
+
+    // measures `sq` and finds `mov` there, which takes the player's turn
+    // the other marks in `sq` are forced into their other squares and so on, like in a collapse
+    // the other half of `mov` just disappears, so anything entangled only through it stays quantum
+    pub fn measure(&mut self, sq: u8, mov: u8) -> Result<CollapseTrace, CollapseError> {
+        let trace = self.measure_trace(sq, mov)?;
+        if let Some(other) = self.other_half(mov, sq) {
+            self.q.remove(mov, sq, other);
+        }
+        self.apply(&trace);
+        self.next_mov += 1;
+        Ok(trace)
+    }
+
+    // measures `sq` with `rng` picking between `measure_chances`
+    pub fn measure_randomly<R: Rng>(
+        &mut self,
+        sq: u8,
+        rng: &mut R,
+    ) -> Result<CollapseTrace, CollapseError> {
+        let chances = self.measure_chances(sq);
+        let mut roll = rng.gen::<f64>();
+        // falls back on the last mark in case the chances add up to just under 1
+        let mov = chances
+            .iter()
+            .find(|&&(_, p)| {
+                roll -= p;
+                roll < 0.0
+            })
+            .or_else(|| chances.last())
+            .map(|&(mov, _)| mov)
+            .ok_or(CollapseError::CannotMeasure(sq))?;
+        self.measure(sq, mov)
+    }
+
+    // works out what `measure` would do without changing the board
+    pub fn measure_trace(&self, sq: u8, mov: u8) -> Result<CollapseTrace, CollapseError> {
+        if !self.cycle.is_empty() {
+            return Err(CollapseError::PendingCycle);
+        }
+        if self.spare_moves() == 0 {
+            return Err(CollapseError::NoMovesLeft);
+        }
+        if sq >= self.cells() || mov >= self.next_mov || !self.q.is(mov, sq) {
+            return Err(CollapseError::NotInSquare { sq, mov });
+        }
+        let other = self.other_half(mov, sq).ok_or(CollapseError::Corrupt {
+            sq,
+            mask: self.q.mask_in(sq),
+        })?;
+        // without a cycle the squares entangled with `sq` form a tree, so once the edge to the other half
+        // of `mov` is gone the resolution reaches everything on this side of it exactly once
+        let mut q = self.q;
+        let mut g = self.g.clone();
+        q.0[other as usize] &= !QuantumBoardState::mask(mov);
+        g.clear_edge(sq, other);
+        collapse::resolve(sq, mov, &mut q, &mut g)
+    }
+
+    // how many more measurements there are move numbers for
+    // every empty square still needs a mark, and each mark that isn't down yet needs its own move number,
+    // so quantum and classical moves always fit in a board's cells, but measuring uses up a move number
+    // without adding a mark and has to stop while there's still room for the rest of the game
+    fn spare_moves(&self) -> u8 {
+        let cells = self.cells();
+        let empty = (0..cells).filter(|&sq| self.c.is_empty(sq)).count() as u8;
+        // the marks already in the empty squares have their move numbers
+        let pending = (0..cells)
+            .fold(0, |marks, sq| marks | self.q.mask_in(sq))
+            .count_ones() as u8;
+        QuantumBoardState::MAX_MOVES.saturating_sub(self.next_mov + empty.saturating_sub(pending))
+    }
+
+    // the square holding the other half of `mov`'s mark in `sq`
+    fn other_half(&self, mov: u8, sq: u8) -> Option<u8> {
+        (0..self.cells()).find(|&other| other != sq && self.q.is(mov, other))
+    }
+
+    // works out what `collapse` would do without changing the board
+    pub fn collapse_trace(&self, sq: u8, mov: u8) -> Result<CollapseTrace, CollapseError> {
+        if self.cycle.is_empty() {
+            return Err(CollapseError::NoCycle);
+        }
+        // find the index of sq in the cycle
+        let idx = self
+            .cycle
+            .iter()
+            .position(|&s| s == sq)
+            .ok_or(CollapseError::NotInCycle(sq))?;
+        if !self.q.is(mov, sq) {
+            return Err(CollapseError::NotCycleMove { sq, mov });
+        }
+
+        // destroy the cycle, making the graph a tree so that we don't backtrack
+        // the edge we cut is the one holding the other half of `mov`: that square can't be reached
+        // straight from this one, it has to be resolved from the other way around the cycle
+        let next = self.cycle[wrap(idx as isize + 1, self.cycle.len())];
+        let prev = self.cycle[wrap(idx as isize - 1, self.cycle.len())];
+        let cut = if self.q.is(mov, next) {
+            next
+        } else if self.q.is(mov, prev) {
+            prev
+        } else {
+            return Err(CollapseError::NotCycleMove { sq, mov });
+        };
+
+        // resolve on copies, they're small enough that this is cheap
+        let mut q = self.q;
+        let mut g = self.g.clone();
+        g.clear_edge(sq, cut);
+        collapse::resolve(sq, mov, &mut q, &mut g)
+    }
+
+    // every classical board this one could end up as if all the pending quantum moves were resolved
+    pub fn worlds(&self) -> Worlds {
+        Worlds::new(self.c, &self.q, self.next_mov, self.rules.players)
+    }
+
+    // counts how often each square is X or O across `worlds()`
+    pub fn world_stats(&self) -> WorldStats {
+        WorldStats::from_worlds(self.worlds())
+    }
+
+    // does the move like `do_move`, but returns what's needed to undo it with `unmake_move`
+    // so random measurements go through `make_measure` instead, the same as with `do_move`
+    pub fn make_move(&mut self, m: Move) -> UndoInfo<N> {
+        let undo = match m {
+            Move::Quantum(sq1, sq2) => UndoInfo::Quantum(sq1, sq2),
+            Move::Classical(sq) => UndoInfo::Classical(sq),
+            Move::Measure(_) => UndoInfo::Measure {
+                c: self.c,
+                q: self.q,
+            },
+            Move::Collapse { .. } => UndoInfo::Collapse {
+                c: self.c,
+                q: self.q,
+                cycle: self.cycle.clone(),
+            },
+        };
+        self.do_move(m);
+        undo
+    }
+
+    // measures `sq` like `measure`, but returns what's needed to undo it with `unmake_move`
+    // this is how a search tries each of the `measure_chances`
+    pub fn make_measure(&mut self, sq: u8, mov: u8) -> Result<UndoInfo<N>, CollapseError> {
+        let undo = UndoInfo::Measure {
+            c: self.c,
+            q: self.q,
+        };
+        self.measure(sq, mov)?;
+        Ok(undo)
+    }
+
+    // restores the board to exactly how it was before the `make_move` that returned `undo`
+    // moves must be unmade in the reverse order they were made
+    pub fn unmake_move(&mut self, undo: UndoInfo<N>) {
+        match undo {
+            UndoInfo::Quantum(sq1, sq2) => {
+                self.next_mov -= 1;
+                self.q.remove(self.next_mov, sq1, sq2);
+                self.g.clear_edge(sq1, sq2);
+                // quantum moves can only be made when there's no cycle
+                self.cycle.clear();
+            }
+            UndoInfo::Classical(sq) => {
+                self.next_mov -= 1;
+                self.c.clear(sq);
+            }
+            UndoInfo::Collapse { c, q, cycle } => {
+                self.restore(c, q);
+                self.cycle = cycle;
+            }
+            UndoInfo::Measure { c, q } => {
+                self.restore(c, q);
+                self.next_mov -= 1;
+            }
+        }
+        debug_assert!(self.is_state_valid());
+    }
+
+    // puts back the classical and quantum boards from before a collapse or measurement
+    fn restore(&mut self, c: ClassicalBoardState, q: QuantumBoardState<N>) {
+        // the collapse removed every edge touching the squares it made classical,
+        // and the number of edges between two squares is the number of moves they share
+        let collapsed = self.c;
+        let cells = self.cells();
+        for sq in (0..cells).filter(|&sq| c.is_empty(sq) && !collapsed.is_empty(sq)) {
+            for other in (0..cells).filter(|&other| other != sq) {
+                let shared = (q.mask_in(sq) & q.mask_in(other)).count_ones();
+                self.g.set_edge_count(sq, other, shared as u8);
             }
         }
+        self.c = c;
+        self.q = q;
+    }
+
+    // like `is_valid`, but also checks that it's `player`'s turn to make the move
+    pub fn is_valid_for(&self, player: u8, m: Move) -> bool {
+        player == self.to_move() && self.is_valid(m)
     }
 
     // this generates all valid moves for this board state. Planned to be used in the AI
+    // they're all made by `to_move()`, which depends on the rules when there's a cycle
     pub fn valid_moves(&self, store: &mut Vec<Move>) {
         store.clear();
         if self.c.game_is_over() {
@@ -474,30 +939,39 @@ impl BoardState {
             let move_mask = (square & next_square) | (square & prev_square);
             // move_mask now contains two high bits -- the two moves that formed the cycle edges in the 0th vertex
             // return them
-            for mov in 0..9 {
+            for mov in 0..self.next_mov {
                 if move_mask & (1 << mov) > 0 {
                     store.push(Move::Collapse {
                         sq: self.cycle[0],
-                        mov: mov,
+                        mov,
                     })
                 }
             }
+        } else if let Some(sq) = self.c.last_square() {
+            // there's no room left for a quantum move, so the last square is filled directly
+            store.push(Move::Classical(sq));
         } else {
             // generate all possible quantum moves, but without duplicates
             // assume all non-classical squares can fit our quantum move
             // this is one of the invariants other code should uphold
             // could optimize the loop
-            for sq1 in 0..9 {
+            let cells = self.cells();
+            for sq1 in 0..cells {
                 if !self.c.is_empty(sq1) {
                     continue;
                 }
-                for sq2 in (sq1 + 1)..9 {
+                for sq2 in (sq1 + 1)..cells {
                     if !self.c.is_empty(sq2) {
                         continue;
                     }
                     store.push(Move::Quantum(sq1, sq2));
                 }
             }
+            if self.rules.measuring != Measuring::Off && self.spare_moves() > 0 {
+                for sq in (0..cells).filter(|&sq| self.q.mask_in(sq) > 0) {
+                    store.push(Move::Measure(sq));
+                }
+            }
         }
 
         store.iter().for_each(|m| debug_assert!(self.is_valid(*m)));
@@ -513,6 +987,9 @@ impl BoardState {
                 if !self.cycle.is_empty() {
                     return false;
                 }
+                if sq1 >= self.cells() || sq2 >= self.cells() {
+                    return false;
+                }
                 // no classical moves
                 if sq1 == sq2 {
                     return false;
@@ -524,17 +1001,24 @@ impl BoardState {
                 if !self.c.is_empty(sq2) {
                     return false;
                 };
-                // then assert the move hasn't been made anywhere before
-                #[cfg(debug)]
-                {
-                    for i in 0..9 {
-                        assert!(!self.q.is(self.next_mov), i);
-                    }
-                }
-
                 true
             }
+            Move::Classical(sq) => {
+                // only when every other square is classical
+                self.cycle.is_empty() && self.c.last_square() == Some(sq)
+            }
+            Move::Measure(sq) => {
+                // instead of a quantum move, so not while there's a cycle, and there has to be something to find
+                self.rules.measuring != Measuring::Off
+                    && self.cycle.is_empty()
+                    && sq < self.cells()
+                    && self.q.mask_in(sq) > 0
+                    && self.spare_moves() > 0
+            }
             Move::Collapse { sq, mov } => {
+                if sq >= self.cells() || mov >= self.next_mov {
+                    return false;
+                }
                 // ensure there is a cycle there
                 let mut store = smallvec::SmallVec::new();
                 if !self.g.has_cycle(sq, &mut store) {
@@ -550,14 +1034,11 @@ impl BoardState {
                 }
 
                 // if this mov is part of the cycle, it should exist in either the next or previous square in the cycle
-                let c_idx = self
-                    .cycle
-                    .iter()
-                    .enumerate()
-                    .filter(|(_idx, &s)| s == sq)
-                    .nth(0)
-                    .unwrap()
-                    .0;
+                // a square on a stem can be part of a cycle's component without being in the cycle
+                let c_idx = match self.cycle.iter().position(|&s| s == sq) {
+                    Some(idx) => idx,
+                    None => return false,
+                };
                 let sq_check_1 = wrap(c_idx as isize - 1, self.cycle.len());
                 let sq_check_2 = wrap(c_idx as isize + 1, self.cycle.len());
 
@@ -574,20 +1055,27 @@ impl BoardState {
     // this checks that the all the different sub-boards (classical, quantum, and the graph) are in agreement
     // used for tests
     pub fn is_state_valid(&self) -> bool {
+        // no square has a mark from a move that hasn't been made yet
+        for i in 0..self.cells() {
+            if u64::from(self.q.mask_in(i)) >> self.next_mov > 0 {
+                return false;
+            }
+        }
+
         // if a square is classical, its graph and quanta must be empty
-        for i in 0..9 {
+        for i in 0..self.cells() {
             if !self.c.is_empty(i) {
                 if self.q.mask_in(i) > 0 {
                     return false;
                 }
-                if self.g.edges()[i as usize] != [0; 9] {
+                if self.g.edges()[i as usize].iter().any(|&ct| ct > 0) {
                     return false;
                 }
             }
         }
 
         // ensure corresponse between graph and quantum states
-        for sq1 in 0..9 {
+        for sq1 in 0..self.cells() {
             for sq2 in self.g.edges()[sq1 as usize]
                 .iter()
                 .enumerate()
@@ -604,6 +1092,12 @@ impl BoardState {
     }
 }
 
+impl Default for BoardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // utility function for iterating over arrays by index, because `%` is division and not modulus
 fn wrap(idx: isize, len: usize) -> usize {
     let len = len as isize;
@@ -614,6 +1108,7 @@ fn wrap(idx: isize, len: usize) -> usize {
 #[cfg(test)]
 mod boardstate_test {
     use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
 
     #[test]
     fn three_cycle_tests() {
@@ -724,6 +1219,133 @@ mod boardstate_test {
         assert!(b.c.is_o(3));
     }
 
+    #[test]
+    fn collapse_trace() {
+        let mut b = BoardState::new();
+        for &(sq1, sq2) in [(0, 1), (1, 2), (3, 4), (4, 3)].iter() {
+            b.do_move(Move::Quantum(sq1, sq2));
+        }
+        // a dry run shouldn't change anything
+        let before = b.clone();
+        let trace = b.collapse_trace(4, 2).unwrap();
+        assert_eq!(b, before);
+        let steps: Vec<_> = trace.iter().map(|a| (a.sq, a.mov)).collect();
+        assert_eq!(steps, vec![(4, 2), (3, 3)]);
+        assert_eq!(b.collapse(4, 2), Ok(trace));
+        assert!(b.c.is_x(4));
+        assert!(b.c.is_o(3));
+        assert!(b.is_state_valid());
+
+        // stems are resolved after the cycle square they hang off
+        b.do_move(Move::Quantum(0, 5));
+        b.do_move(Move::Quantum(5, 6));
+        b.do_move(Move::Quantum(2, 0));
+        assert!(b.has_cycle());
+        let steps: Vec<_> = b
+            .collapse(0, 0)
+            .unwrap()
+            .iter()
+            .map(|a| (a.sq, a.mov))
+            .collect();
+        assert_eq!(steps, vec![(0, 0), (2, 6), (1, 1), (5, 4), (6, 5)]);
+        assert!(b.is_state_valid());
+    }
+
+    #[test]
+    fn final_square_classical() {
+        let mut b = BoardState::new();
+        for &(sq1, sq2) in [(1, 0), (2, 1), (2, 0), (3, 4), (4, 5), (5, 3)].iter() {
+            b.do_move(Move::Quantum(sq1, sq2));
+            if b.has_cycle() {
+                let mut v = Vec::new();
+                b.valid_moves(&mut v);
+                b.do_move(v[0]);
+            }
+        }
+        b.do_move(Move::Quantum(6, 7));
+        assert!(!b.is_valid(Move::Classical(8)));
+        b.do_move(Move::Quantum(7, 6));
+        b.do_move(Move::Collapse { sq: 6, mov: 6 });
+        // everything but square 8 is classical, and nobody has won yet
+        assert!(!b.classic().x_wins() && !b.classic().o_wins());
+        assert!(!b.classic().game_is_over());
+        assert_eq!(b.classic().last_square(), Some(8));
+        let mut v = Vec::new();
+        b.valid_moves(&mut v);
+        assert_eq!(v, vec![Move::Classical(8)]);
+        assert!(!b.is_valid(Move::Classical(7)));
+        assert!(!b.is_valid(Move::Quantum(7, 8)));
+
+        let before = b.clone();
+        let undo = b.make_move(Move::Classical(8));
+        // X's 9th move fills the board without completing a line
+        assert!(b.classic().is_x(8));
+        assert!(!b.classic().has_winner());
+        assert!(b.classic().game_is_over());
+        b.valid_moves(&mut v);
+        assert!(v.is_empty());
+        b.unmake_move(undo);
+        assert_eq!(b, before);
+    }
+
+    #[test]
+    fn collapse_chooser() {
+        let mut b = BoardState::new();
+        assert_eq!(b.collapse_chooser(), None);
+        assert_eq!(b.to_move(), 0);
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(1, 2));
+        assert_eq!(b.to_move(), 0);
+        // X closes the cycle
+        b.do_move(Move::Quantum(2, 0));
+        let creator = b.clone().with_rules(RuleSet {
+            collapse_chooser: CollapseChooser::Creator,
+            ..RuleSet::default()
+        });
+
+        // by default O picks the collapse, then it's O's quantum move
+        assert_eq!(b.collapse_chooser(), Some(1));
+        assert!(b.is_valid_for(1, Move::Collapse { sq: 0, mov: 0 }));
+        assert!(!b.is_valid_for(0, Move::Collapse { sq: 0, mov: 0 }));
+        b.do_move(Move::Collapse { sq: 0, mov: 0 });
+        assert_eq!(b.to_move(), 1);
+
+        // otherwise X picks, and O still moves next
+        assert_eq!(creator.collapse_chooser(), Some(0));
+        assert_eq!(creator.to_move(), 0);
+        let mut v = Vec::new();
+        creator.valid_moves(&mut v);
+        assert!(v.iter().all(|&m| creator.is_valid_for(0, m)));
+        let mut creator = creator;
+        creator.do_move(Move::Collapse { sq: 0, mov: 0 });
+        assert_eq!(creator.collapse_chooser(), None);
+        assert_eq!(creator.to_move(), 1);
+    }
+
+    #[test]
+    fn collapse_errors() {
+        let mut b = BoardState::new();
+        assert_eq!(b.collapse_trace(0, 0), Err(CollapseError::NoCycle));
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(1, 2));
+        b.do_move(Move::Quantum(3, 4));
+        b.do_move(Move::Quantum(2, 0));
+        assert_eq!(b.collapse_trace(3, 2), Err(CollapseError::NotInCycle(3)));
+        assert_eq!(
+            b.collapse_trace(0, 1),
+            Err(CollapseError::NotCycleMove { sq: 0, mov: 1 })
+        );
+
+        // knock a mark out from under the cycle, the collapse should fail without touching the board
+        b.q.0[1] &= !QuantumBoardState::mask(1);
+        let before = b.clone();
+        assert_eq!(
+            b.collapse(0, 0),
+            Err(CollapseError::Corrupt { sq: 1, mask: 0 })
+        );
+        assert_eq!(b, before);
+    }
+
     #[test]
     fn win_conditions() {
         let mut c = ClassicalBoardState::new();
@@ -736,4 +1358,412 @@ mod boardstate_test {
         assert!(c.x_wins());
         // add more eventually
     }
+
+    // tiny xorshift generator so the random games are the same on every run
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    // the same position built with `from_marks` instead of played to
+    fn rebuilt<const N: usize>(b: &BoardState<N>) -> BoardState<N> {
+        let marks: Vec<(u8, u8, u8)> = (0..b.next_mov())
+            .filter_map(|mov| {
+                let mut squares = (0..b.cells()).filter(|&sq| b.quantum().is(mov, sq));
+                Some((mov, squares.next()?, squares.next()?))
+            })
+            .collect();
+        BoardState::from_marks(*b.classic(), &marks, b.next_mov()).with_rules(b.rules())
+    }
+
+    // plays random games from `start`, checking make/unmake against clones at every step
+    fn round_trip_games<const N: usize>(start: BoardState<N>, games: usize) {
+        let mut rng = 0x2545_f491_4f6c_dd1d;
+        let mut v = Vec::with_capacity(500);
+        for _game in 0..games {
+            let mut b = start.clone();
+            let mut history = Vec::new();
+            b.valid_moves(&mut v);
+            while !v.is_empty() {
+                let m = v[xorshift(&mut rng) as usize % v.len()];
+                // random measurements pick which mark they find here, the way `measure_randomly` would
+                let found = match m {
+                    Move::Measure(sq) if b.rules().measuring == Measuring::Random => {
+                        let chances = b.measure_chances(sq);
+                        Some((sq, chances[xorshift(&mut rng) as usize % chances.len()].0))
+                    }
+                    _ => None,
+                };
+                let make = |b: &mut BoardState<N>| match found {
+                    Some((sq, mov)) => b.make_measure(sq, mov).unwrap(),
+                    None => b.make_move(m),
+                };
+                // making and unmaking should leave the board untouched
+                let before = b.clone();
+                let undo = make(&mut b);
+                b.unmake_move(undo);
+                assert_eq!(b, before);
+                assert_eq!(b.key(), before.key());
+                // and making should do the same thing as do_move or measure
+                let mut expected = b.clone();
+                match found {
+                    Some((sq, mov)) => {
+                        expected.measure(sq, mov).unwrap();
+                    }
+                    None => expected.do_move(m),
+                }
+                history.push((before, make(&mut b)));
+                assert_eq!(b, expected);
+                assert!(b.is_state_valid());
+                b.valid_moves(&mut v);
+            }
+            assert!(b.classic().game_is_over());
+            // unwinding the whole game should pass back through every position
+            while let Some((before, undo)) = history.pop() {
+                b.unmake_move(undo);
+                assert_eq!(b, before);
+                // the key comes back with the board, and building the board from its marks gives the same key
+                assert_eq!(b.key(), before.key());
+                assert_eq!(rebuilt(&b).key(), b.key());
+                assert_eq!(rebuilt(&b), b);
+            }
+            assert_eq!(b, start);
+        }
+    }
+
+    #[test]
+    fn make_unmake_round_trip() {
+        round_trip_games(BoardState::new(), 500);
+    }
+
+    fn measuring(measuring: Measuring) -> BoardState {
+        BoardState::new().with_rules(RuleSet {
+            measuring,
+            ..RuleSet::default()
+        })
+    }
+
+    #[test]
+    fn measure_round_trip() {
+        round_trip_games(measuring(Measuring::Oldest), 300);
+        round_trip_games(measuring(Measuring::Random), 300);
+    }
+
+    #[test]
+    fn measure_stems() {
+        // a chain 1-2-3-4, with X1 in 1 and 2, O2 in 2 and 3, and X3 in 3 and 4
+        let mut b = measuring(Measuring::Oldest);
+        for &m in [
+            Move::Quantum(0, 1),
+            Move::Quantum(1, 2),
+            Move::Quantum(2, 3),
+        ]
+        .iter()
+        {
+            b.do_move(m);
+        }
+        assert!(b.is_valid(Move::Measure(1)));
+        assert!(!b.is_valid(Move::Measure(5)));
+        assert!(!BoardState::new().is_valid(Move::Measure(1)));
+        assert_eq!(b.measure_chances(1).as_slice(), &[(0, 1.0)]);
+
+        // finding X1 in square 2 pushes O2 into 3, and X3 into 4
+        // square 1 lost its only mark, so it's empty again
+        let before = b.clone();
+        let undo = b.make_move(Move::Measure(1));
+        assert!(b.classic().is_x(1) && b.classic().is_o(2) && b.classic().is_x(3));
+        assert!(b.classic().is_empty(0));
+        assert_eq!(b.quantum().mask_in(0), 0);
+        // it took O's turn
+        assert_eq!(b.next_mov(), 4);
+        assert!(b.is_state_valid());
+        b.unmake_move(undo);
+        assert_eq!(b, before);
+
+        // finding O2 in square 2 instead pushes X1 into 1, but X3 is only entangled through O2's other half,
+        // so it stays quantum
+        let trace = b.measure(1, 1).unwrap();
+        let steps: Vec<(u8, u8)> = trace.iter().map(|a| (a.sq, a.mov)).collect();
+        assert_eq!(steps, vec![(1, 1), (0, 0)]);
+        assert!(b.classic().is_empty(2) && b.classic().is_empty(3));
+        assert!(b.quantum().is(2, 2) && b.quantum().is(2, 3));
+        assert!(!b.quantum().is(1, 2));
+        assert!(b.is_state_valid());
+        assert_eq!(
+            b.measure(1, 1),
+            Err(CollapseError::NotInSquare { sq: 1, mov: 1 })
+        );
+    }
+
+    #[test]
+    fn measuring_runs_out_of_moves() {
+        // the cube has 27 squares, so measuring more than 5 times would need more than 32 move numbers
+        let start = BoardState::<27>::with_topology(Topology::cube()).with_rules(RuleSet {
+            measuring: Measuring::Random,
+            ..RuleSet::default()
+        });
+        let mut rng = 0x9e37_79b9_7f4a_7c15;
+        let mut v = Vec::new();
+        let mut refused = 0;
+        for _game in 0..200 {
+            let mut b = start.clone();
+            b.valid_moves(&mut v);
+            while !v.is_empty() {
+                let measures: Vec<u8> = v
+                    .iter()
+                    .filter_map(|&m| match m {
+                        Move::Measure(sq) => Some(sq),
+                        _ => None,
+                    })
+                    .collect();
+                // there are marks to measure, but no move numbers to spare for it
+                let marked = (0..27).find(|&sq| b.quantum().mask_in(sq) > 0);
+                if let (true, false, Some(sq)) = (measures.is_empty(), b.has_cycle(), marked) {
+                    let mov = b.quantum().mask_in(sq).trailing_zeros() as u8;
+                    assert_eq!(b.measure_trace(sq, mov), Err(CollapseError::NoMovesLeft));
+                    refused += 1;
+                }
+                // measure three times in four when there's something to measure, finding a random mark
+                let m = if !measures.is_empty() && xorshift(&mut rng) % 4 < 3 {
+                    Move::Measure(measures[xorshift(&mut rng) as usize % measures.len()])
+                } else {
+                    v[xorshift(&mut rng) as usize % v.len()]
+                };
+                match m {
+                    Move::Measure(sq) => {
+                        let chances = b.measure_chances(sq);
+                        let (mov, _) = chances[xorshift(&mut rng) as usize % chances.len()];
+                        b.measure(sq, mov).unwrap();
+                    }
+                    m => b.do_move(m),
+                }
+                assert!(b.next_mov() <= QuantumBoardState::MAX_MOVES);
+                assert!(b.is_state_valid());
+                b.valid_moves(&mut v);
+            }
+            assert!(b.classic().game_is_over());
+        }
+        // some of the games should have run out of measurements before they ended
+        assert!(refused > 0);
+    }
+
+    // this is an `assert!`, so it holds in release builds too
+    #[test]
+    #[should_panic(expected = "measure_randomly")]
+    fn random_measure_needs_an_rng() {
+        let mut b = measuring(Measuring::Random);
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Measure(0));
+    }
+
+    #[test]
+    fn measured_marks_are_sound() {
+        // each measurement takes a move number, so five of them push the next mark past move 9
+        let mut b = measuring(Measuring::Oldest);
+        for &sq in [0, 1, 5, 6, 7].iter() {
+            let mov = b.next_mov();
+            b.do_move(Move::Quantum(sq, 8));
+            b.measure(sq, mov).unwrap();
+        }
+        b.do_move(Move::Quantum(2, 3));
+        assert!(b.quantum().is(10, 2) && b.quantum().is(10, 3));
+        assert!(!b.classic().game_is_over());
+        assert!(b.quantum().is_sound());
+        assert!(b.is_state_valid());
+    }
+
+    #[test]
+    fn measure_components() {
+        // two separate components: 1-2-3 and 7-8
+        let mut b = measuring(Measuring::Random);
+        for &m in [
+            Move::Quantum(0, 1),
+            Move::Quantum(1, 2),
+            Move::Quantum(6, 7),
+        ]
+        .iter()
+        {
+            b.do_move(m);
+        }
+        let chances = b.measure_chances(1);
+        assert_eq!(chances.as_slice(), &[(0, 0.5), (1, 0.5)]);
+        let mut v = Vec::new();
+        b.valid_moves(&mut v);
+        let measures: Vec<Move> = v
+            .into_iter()
+            .filter(|m| matches!(m, Move::Measure(_)))
+            .collect();
+        let squares = [0, 1, 2, 6, 7];
+        assert_eq!(
+            measures,
+            squares
+                .iter()
+                .map(|&sq| Move::Measure(sq))
+                .collect::<Vec<_>>()
+        );
+
+        // measuring one component leaves the other alone
+        let trace = b.measure(7, 2).unwrap();
+        assert_eq!(trace.len(), 1);
+        assert!(b.classic().is_x(7) && b.classic().is_empty(6));
+        assert!(b.quantum().is(0, 0) && b.quantum().is(1, 1) && b.quantum().is(1, 2));
+        assert!(b.is_state_valid());
+
+        // and measuring isn't allowed while a cycle is waiting
+        b.do_move(Move::Quantum(2, 0));
+        assert!(b.has_cycle());
+        assert!(!b.is_valid(Move::Measure(0)));
+        assert!(b.measure_chances(0).is_empty());
+        assert_eq!(b.measure(0, 0), Err(CollapseError::PendingCycle));
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            b.measure_randomly(0, &mut rng),
+            Err(CollapseError::CannotMeasure(0))
+        );
+    }
+
+    #[test]
+    fn larger_boards() {
+        round_trip_games(
+            BoardState::<16>::with_topology(Topology::grid(4, 3).leak()),
+            100,
+        );
+        round_trip_games(
+            BoardState::<16>::with_topology(Topology::grid(4, 4).leak()),
+            100,
+        );
+        round_trip_games(
+            BoardState::<25>::with_topology(Topology::grid(5, 4).leak()),
+            50,
+        );
+        round_trip_games(BoardState::<27>::with_topology(Topology::cube()), 20);
+    }
+
+    #[test]
+    fn loaded_boards() {
+        let torus = Topology::load("boards/torus.txt").unwrap().leak();
+        round_trip_games(BoardState::<9>::with_topology(torus), 100);
+        round_trip_games(BoardState::<32>::with_topology(torus), 20);
+        let magic = Topology::load("boards/magic15.txt").unwrap().leak();
+        round_trip_games(BoardState::<9>::with_topology(magic), 100);
+
+        // a diagonal that wraps around the edge of the torus
+        let mut b = BoardState::<9>::with_topology(torus);
+        b.do_move(Move::Quantum(1, 5));
+        b.do_move(Move::Quantum(0, 3));
+        b.do_move(Move::Quantum(5, 6));
+        b.do_move(Move::Quantum(3, 4));
+        b.do_move(Move::Quantum(6, 1));
+        b.do_move(Move::Collapse { sq: 1, mov: 0 });
+        assert!(b.classic().is_x(1) && b.classic().is_x(5) && b.classic().is_x(6));
+        assert!(b.classic().x_wins());
+        assert!(b.classic().game_is_over());
+    }
+
+    fn three_players() -> BoardState<16> {
+        BoardState::<16>::with_topology(Topology::grid(4, 3).leak()).with_rules(RuleSet {
+            players: 3,
+            ..RuleSet::default()
+        })
+    }
+
+    #[test]
+    fn three_player_turns() {
+        let mut b = three_players();
+        let turns: Vec<u8> = (0..4).map(|mov| b.player_of(mov)).collect();
+        assert_eq!(turns, vec![0, 1, 2, 0]);
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(1, 2));
+        assert_eq!(b.to_move(), 2);
+        // Y closes the cycle, and the next player, X, picks the collapse
+        b.do_move(Move::Quantum(2, 0));
+        assert_eq!(b.collapse_chooser(), Some(0));
+        let creator = b.clone().with_rules(RuleSet {
+            collapse_chooser: CollapseChooser::Creator,
+            ..b.rules()
+        });
+        assert_eq!(creator.collapse_chooser(), Some(2));
+        let text = text::render(&b, &[], None);
+        assert!(text.contains("3:O2Y3"));
+
+        b.do_move(Move::Collapse { sq: 0, mov: 0 });
+        assert_eq!(
+            (0..3)
+                .map(|sq| b.classic().player_at(sq))
+                .collect::<Vec<_>>(),
+            vec![Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(b.to_move(), 0);
+        // the third player's marks count for them alone
+        let mut c = *b.classic();
+        assert!(!c.has_winner());
+        c.set(6, 2);
+        assert!(!c.wins(2));
+        c.set(10, 2);
+        assert!(c.wins(2) && !c.x_wins() && !c.o_wins());
+        assert!(c.game_is_over());
+    }
+
+    #[test]
+    fn three_player_games() {
+        round_trip_games(three_players(), 100);
+    }
+
+    #[test]
+    fn larger_win_conditions() {
+        let topo = Topology::grid(4, 3).leak();
+        let mut c = ClassicalBoardState::with_topology(topo);
+        // a diagonal that doesn't touch a corner
+        c.set_o(1);
+        c.set_o(6);
+        assert!(!c.o_wins());
+        c.set_o(11);
+        assert!(c.o_wins());
+        assert!(!c.x_wins());
+
+        let mut c = ClassicalBoardState::with_topology(Topology::grid(5, 5).leak());
+        for sq in 0..4 {
+            c.set_x(sq * 5);
+        }
+        assert!(!c.x_wins());
+        c.set_x(20);
+        assert!(c.x_wins());
+        // with 15 of 16 squares filled the last one can still be played classically
+        let mut c = ClassicalBoardState::with_topology(topo);
+        for sq in [0, 1, 6, 7, 8, 9, 14].iter() {
+            c.set_x(*sq);
+        }
+        for sq in [2, 3, 4, 5, 10, 11, 12, 13].iter() {
+            c.set_o(*sq);
+        }
+        assert!(!c.has_winner());
+        assert!(!c.game_is_over());
+        assert_eq!(c.last_square(), Some(15));
+        c.set_x(15);
+        assert!(!c.has_winner());
+        assert!(c.game_is_over());
+    }
+
+    #[test]
+    fn larger_board_collapse() {
+        let mut b = BoardState::<16>::with_topology(Topology::grid(4, 4).leak());
+        b.do_move(Move::Quantum(0, 15));
+        b.do_move(Move::Quantum(15, 12));
+        b.do_move(Move::Quantum(12, 3));
+        b.do_move(Move::Quantum(3, 0));
+        assert!(b.has_cycle());
+        let mut v = Vec::new();
+        b.valid_moves(&mut v);
+        assert_eq!(v.len(), 2);
+        b.do_move(Move::Collapse { sq: 0, mov: 0 });
+        assert!(b.classic().is_x(0));
+        assert!(b.classic().is_o(15));
+        assert!(b.classic().is_x(12));
+        assert!(b.classic().is_o(3));
+        assert!(b.is_state_valid());
+        // squares past the end of the board aren't valid
+        assert!(!b.is_valid(Move::Quantum(1, 16)));
+    }
 }
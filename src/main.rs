@@ -1,138 +1,687 @@
 use ansi_escapes::EraseScreen;
+use book::Book;
+use eval::{Evaluator, Weights};
 use lazy_static::lazy_static;
+use network::Network;
+use parallel::ParallelSearch;
 use qtictac_ai::*;
+use rand::{rngs::StdRng, SeedableRng};
 use regex::Regex;
+use search::{Clock, Limits};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tablebase::Tablebase;
+use td::TdAgent;
+
+// draws a board, with a collapse in progress and the heatmap if they're given, see `render_board`
+type Render<const N: usize> = dyn Fn(&BoardState<N>, &[Assignment], Option<&WorldStats>) -> String;
 
 fn main() {
-    let mut b = BoardState::new();
     let mut input = String::new();
-    let stdin = std::io::stdin();
     println!(include_str!("../instructions.txt"));
-    stdin.read_line(&mut input).unwrap(); // wait for user acknowledgement
+    std::io::stdin().read_line(&mut input).unwrap(); // wait for user acknowledgement
+
+    let args: Vec<String> = std::env::args().collect();
+    // `--creator-collapses` lets the player who closes a cycle choose how it collapses
+    let mut rules = RuleSet::default();
+    if args.iter().any(|arg| arg == "--creator-collapses") {
+        rules.collapse_chooser = CollapseChooser::Creator;
+    }
+    // `--misere` makes completing a row lose
+    rules.misere = args.iter().any(|arg| arg == "--misere");
+    // `--measure` collapses cycles at random instead of letting a player choose
+    // it can be followed by the percent chance the newest mark lands in its lower square, and `--seed <n>`
+    // makes the measurements the same every game
+    if let Some(idx) = args.iter().position(|arg| arg == "--measure") {
+        let weight = args.get(idx + 1).and_then(|arg| arg.parse().ok());
+        rules.collapse_chooser = CollapseChooser::Measurement {
+            weight: weight.unwrap_or(50),
+        };
+    }
+    // `--measuring oldest` or `--measuring random` lets players spend a turn measuring a square
+    if let Some(idx) = args.iter().position(|arg| arg == "--measuring") {
+        rules.measuring = match args.get(idx + 1).map(String::as_str) {
+            Some("oldest") => Measuring::Oldest,
+            Some("random") => Measuring::Random,
+            _ => return println!("--measuring needs to be followed by oldest or random"),
+        };
+    }
+    let mut rng = match args.iter().position(|arg| arg == "--seed") {
+        Some(idx) => match args.get(idx + 1).and_then(|arg| arg.parse().ok()) {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => return println!("--seed needs a number"),
+        },
+        None => StdRng::from_entropy(),
+    };
+    // `--computer <seconds>` has the computer play O, with that many seconds on its clock for the game
+    let computer = match args.iter().position(|arg| arg == "--computer") {
+        Some(idx) => match args.get(idx + 1).and_then(|arg| arg.parse().ok()) {
+            Some(seconds) => Some(Clock {
+                remaining: Duration::from_secs(seconds),
+                increment: Duration::from_millis(500),
+            }),
+            None => return println!("--computer needs a number of seconds"),
+        },
+        None => None,
+    };
+    // `--tablebase <file>` loads a tablebase from the `build_tablebase` example for "?" to give hints from
+    let tablebase = match args.iter().position(|arg| arg == "--tablebase") {
+        Some(idx) => match args.get(idx + 1).map(Tablebase::load) {
+            Some(Ok(tablebase)) if tablebase.rules() == rules => Some(Arc::new(tablebase)),
+            Some(Ok(_)) => return println!("the tablebase was built for different rules"),
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--tablebase needs a file to load"),
+        },
+        None => None,
+    };
+    // `--book <file>` gives the computer an opening book from the `build_book` example to play from
+    let book = match args.iter().position(|arg| arg == "--book") {
+        Some(idx) => match args.get(idx + 1).map(Book::load) {
+            Some(Ok(book)) if book.rules() == rules => Some(Arc::new(book)),
+            Some(Ok(_)) => return println!("the opening book was built for different rules"),
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--book needs a file to load"),
+        },
+        None => None,
+    };
+    // `--weights <file>` changes how the computer values positions it can't search to the end of
+    let weights = match args.iter().position(|arg| arg == "--weights") {
+        Some(idx) => match args.get(idx + 1).map(Weights::load) {
+            Some(Ok(weights)) => weights,
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--weights needs a file to load"),
+        },
+        None => Weights::default(),
+    };
+    // `--network <file>` has the computer use a network from the `train_network` example instead
+    let evaluator = match args.iter().position(|arg| arg == "--network") {
+        Some(idx) => match args.get(idx + 1).map(Network::load) {
+            Some(Ok(network)) => Evaluator::Network(Arc::new(network)),
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--network needs a file to load"),
+        },
+        None => Evaluator::Weights(weights),
+    };
+    // `--learned <file>` has O played by a value table from the `td_learning` example, without searching
+    let learned = match args.iter().position(|arg| arg == "--learned") {
+        Some(idx) => match args.get(idx + 1).map(TdAgent::load) {
+            Some(Ok(agent)) if agent.rules() == rules => Some(agent),
+            Some(Ok(_)) => return println!("the value table was learned with different rules"),
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--learned needs a file to load"),
+        },
+        None => None,
+    };
+    let assist = Assist {
+        computer,
+        tablebase,
+        book,
+        evaluator,
+        learned,
+    };
+    // `--board <file>` plays on a board loaded from a definition file, see `Topology::parse`
+    if let Some(idx) = args.iter().position(|arg| arg == "--board") {
+        let topo = match args.get(idx + 1).map(Topology::load) {
+            Some(Ok(topo)) => topo.leak(),
+            Some(Err(e)) => return println!("{}", e),
+            None => return println!("--board needs a file to load"),
+        };
+        // room for the biggest board there can be
+        let b = BoardState::<{ Topology::MAX_CELLS }>::with_topology(topo).with_rules(rules);
+        if topo.cells() > 9 {
+            play(b, &text::render, nums_from_input, &mut rng, assist);
+        } else {
+            play(b, &text::render, two_num_from_input, &mut rng, assist);
+        }
+    // `--three-players` adds Y as a third player, on a 4x4 board where 3 in a row wins
+    } else if args.iter().any(|arg| arg == "--three-players") {
+        rules.players = 3;
+        let topo = Topology::grid(4, 3).leak();
+        let b = BoardState::<16>::with_topology(topo).with_rules(rules);
+        // the search and tablebases only know about two players
+        play(
+            b,
+            &text::render,
+            nums_from_input,
+            &mut rng,
+            Assist::default(),
+        );
+    // `--ultimate` plays nine boards at once, see `UltimateBoard`
+    } else if args.iter().any(|arg| arg == "--ultimate") {
+        // the sub-boards are always played by the normal rules
+        if rules != RuleSet::default() {
+            return println!(
+                "--ultimate can't be played with --misere, --creator-collapses, --measure or --measuring"
+            );
+        }
+        play_ultimate();
+    // `--cube` plays on a 3x3x3 cube instead of the normal board
+    } else if args.iter().any(|arg| arg == "--cube") {
+        let b = BoardState::<27>::with_topology(Topology::cube()).with_rules(rules);
+        play(b, &text::render, nums_from_input, &mut rng, assist);
+    } else {
+        play(
+            BoardState::new().with_rules(rules),
+            &|b, collapsed, heat| render_board(b, collapsed, heat).unwrap(),
+            two_num_from_input,
+            &mut rng,
+            assist,
+        );
+    }
+}
+
+// what the computer adds to a game
+struct Assist {
+    // with a clock, O's moves are searched for instead, taking a share of the time left each move
+    computer: Option<Clock>,
+    // exact results near the end of the game, for hints and for the computer to use
+    tablebase: Option<Arc<Tablebase>>,
+    // moves for the computer to play at the start of the game without searching
+    book: Option<Arc<Book>>,
+    // how the computer values positions it can't search to the end of
+    evaluator: Evaluator,
+    // plays O by what it learned instead of searching, even with a clock
+    learned: Option<TdAgent>,
+}
+
+impl Default for Assist {
+    fn default() -> Self {
+        Assist {
+            computer: None,
+            tablebase: None,
+            book: None,
+            evaluator: Evaluator::Weights(Weights::default()),
+            learned: None,
+        }
+    }
+}
+
+// runs a game between two people at the keyboard
+// `parse` reads the two numbers of a move from what they type, and `rng` does any measuring
+fn play<const N: usize>(
+    mut b: BoardState<N>,
+    render: &Render<N>,
+    parse: fn(&str) -> Option<(u8, u8)>,
+    rng: &mut StdRng,
+    assist: Assist,
+) {
+    let mut input = String::new();
+    let stdin = std::io::stdin();
+
+    // whether to show how likely each square is to end up X or O
+    let mut heatmap = false;
+    // shown under the board until the next move: what the tablebase said about the position after
+    // asking it with "?", or that the computer's last move came from the book
+    let mut hint: Option<String> = None;
+    // a network has inputs for each square, so it can't evaluate a board of another size
+    if let (Evaluator::Network(network), Some(_)) = (&assist.evaluator, assist.computer) {
+        if network.cells() != b.cells() {
+            return println!(
+                "the network was trained for boards with {} squares",
+                network.cells()
+            );
+        }
+    }
+    // the values were learned for positions on the normal board
+    if assist.learned.is_some() && !std::ptr::eq(b.topology(), Topology::classic()) {
+        return println!("the value table was learned on the normal 3x3 board");
+    }
+    // the computer searches on every core, and keeps what it's found from one move to the next
+    let mut computer = assist.computer;
+    let engine = computer.map(|_| {
+        let mut engine = ParallelSearch::new(0).with_evaluation(assist.evaluator.clone());
+        if let Some(tablebase) = &assist.tablebase {
+            engine = engine.with_tablebase(tablebase.clone());
+        }
+        if let Some(book) = &assist.book {
+            engine = engine.with_book(book.clone());
+        }
+        engine
+    });
 
     while !b.classic().game_is_over() {
+        // nobody chooses how a measured cycle collapses, so it's shown happening on its own
+        if b.collapse_chances().is_some() {
+            let before = b.clone();
+            if let Ok(trace) = b.measure_cycle(rng) {
+                animate_collapse(&before, &trace, render);
+                continue;
+            }
+        }
+        let computer_move = if let (Some(agent), 1) = (&assist.learned, b.to_move()) {
+            hint = None;
+            Some(agent.best_move(&b))
+        } else if let (Some(clock), Some(engine)) =
+            (computer.as_mut().filter(|_| b.to_move() == 1), &engine)
+        {
+            let start = Instant::now();
+            let limits = Limits {
+                time: Some(clock.allocate(&b)),
+                ..Limits::default()
+            };
+            let found = engine.search(&b, limits);
+            hint = if found.book {
+                Some(format!(
+                    "{} played a book move",
+                    MARKS[b.to_move() as usize]
+                ))
+            } else {
+                None
+            };
+            clock.remaining = clock.remaining.saturating_sub(start.elapsed()) + clock.increment;
+            Some(found.best)
+        } else {
+            None
+        };
+        if let Some(best) = computer_move {
+            let before = b.clone();
+            match best {
+                Some(Move::Collapse { sq, mov }) => {
+                    if let Ok(trace) = b.collapse(sq, mov) {
+                        animate_collapse(&before, &trace, render);
+                    }
+                }
+                Some(Move::Measure(sq)) => {
+                    if let Ok(trace) = b.measure_randomly(sq, rng) {
+                        animate_collapse(&before, &trace, render);
+                    }
+                }
+                Some(m) => b.do_move(m),
+                None => break,
+            }
+            continue;
+        }
         // array literal thats indexed by the move
-        let mover = ['X', 'O'][b.next_mov() as usize % 2];
+        let mover = MARKS[b.to_move() as usize];
         let mut has_tried = false;
         loop {
             println!("{}", EraseScreen);
-            println!("{}", render_board(&b).unwrap()); // this call uses a format syntax, where each "{}" is replaced with the corresponding argument
+            let stats = if heatmap { Some(b.world_stats()) } else { None };
+            println!("{}", render(&b, &[], stats.as_ref())); // this call uses a format syntax, where each "{}" is replaced with the corresponding argument
+            if let Some(hint) = &hint {
+                println!("{}", hint);
+            }
             if has_tried {
                 print!("Invalid move! ");
             }
+            // with one empty square left there's nothing to pair it with, so it's played classically
+            if let (Some(last), false) = (b.classic().last_square(), b.has_cycle()) {
+                println!(
+                    "{}'s move. Only square {} is left, enter it to place a classical mark",
+                    mover,
+                    last + 1
+                );
+                input.clear();
+                stdin.read_line(&mut input).unwrap();
+                if input.trim() == "h" {
+                    heatmap = !heatmap;
+                    has_tried = false;
+                    continue;
+                }
+                if let (true, Some(tablebase)) = (input.trim() == "?", &assist.tablebase) {
+                    hint = Some(tablebase_hint(tablebase, &b));
+                    has_tried = false;
+                    continue;
+                }
+                if let Some(sq) = num_from_input(&input).filter(|&sq| sq > 0) {
+                    if b.is_valid(Move::Classical(sq - 1)) {
+                        b.do_move(Move::Classical(sq - 1));
+                        break;
+                    }
+                }
+                has_tried = true;
+                continue;
+            }
+            let measuring = b.rules().measuring != Measuring::Off && !b.has_cycle();
             println!(
-                "{}{}",
+                "{}{}{} (\"h\" toggles the heatmap{})",
                 mover,
                 [
                     "'s move. (\"square1, square2\")",
                     " must resolve the cycle! (\"square, move to collapse to\")"
-                ][if b.has_cycle() { 1 } else { 0 }]
+                ][if b.has_cycle() { 1 } else { 0 }],
+                if measuring {
+                    " or measure (\"m square\")"
+                } else {
+                    ""
+                },
+                if assist.tablebase.is_some() {
+                    ", \"?\" asks the tablebase"
+                } else {
+                    ""
+                }
             );
             input.clear();
             stdin.read_line(&mut input).unwrap();
-            match two_num_from_input(&input) {
-                Some((first, second)) => {
-                    let mov = if b.has_cycle() {
-                        Move::Collapse {
-                            sq: first - 1,
-                            mov: second - 1,
+            if input.trim() == "h" {
+                heatmap = !heatmap;
+                has_tried = false;
+                continue;
+            }
+            if let (true, Some(tablebase)) = (input.trim() == "?", &assist.tablebase) {
+                hint = Some(tablebase_hint(tablebase, &b));
+                has_tried = false;
+                continue;
+            }
+            if measuring && input.trim_start().starts_with('m') {
+                if let Some(sq) = num_from_input(&input).filter(|&sq| sq > 0) {
+                    let before = b.clone();
+                    if let Ok(trace) = b.measure_randomly(sq - 1, rng) {
+                        animate_collapse(&before, &trace, render);
+                        break;
+                    }
+                }
+                has_tried = true;
+                continue;
+            }
+            if let Some((first, second)) = parse(&input).filter(|&(a, b)| a > 0 && b > 0) {
+                let mov = if b.has_cycle() {
+                    Move::Collapse {
+                        sq: first - 1,
+                        mov: second - 1,
+                    }
+                } else {
+                    Move::Quantum(first - 1, second - 1)
+                };
+                if b.is_valid(mov) {
+                    if let Move::Collapse { sq, mov } = mov {
+                        // keep the board from before so the collapse can be replayed on top of it
+                        let before = b.clone();
+                        if let Ok(trace) = b.collapse(sq, mov) {
+                            animate_collapse(&before, &trace, render);
+                            break;
                         }
                     } else {
-                        Move::Quantum(first - 1, second - 1)
-                    };
-                    if b.is_valid(mov) {
                         b.do_move(mov);
                         break;
                     }
                 }
-                _ => {}
             }
             has_tried = true;
         }
+        // it was about the position before the move
+        hint = None;
+    }
+    println!("{}", EraseScreen);
+    println!("{}", render(&b, &[], None));
+    match b.outcome() {
+        Some(Outcome::Win(player)) => println!("{} wins!", MARKS[player as usize]),
+        Some(Outcome::Loss(player)) => println!("{} loses!", MARKS[player as usize]),
+        _ => println!("Tie game!"),
+    }
+}
+
+// what the tablebase says will happen, and the move that gets there, in the words the player would type it
+fn tablebase_hint<const N: usize>(tablebase: &Tablebase, b: &BoardState<N>) -> String {
+    let (m, solution) = match tablebase.best_move(b) {
+        Some(found) => found,
+        None => return "The tablebase doesn't have this position".to_string(),
+    };
+    let result = match solution.value {
+        1 => "X wins",
+        -1 => "O wins",
+        _ => "It's a tie",
+    };
+    let mov = match m {
+        Move::Quantum(sq1, sq2) => format!("{}, {}", sq1 + 1, sq2 + 1),
+        Move::Collapse { sq, mov } => format!("{}, {}", sq + 1, mov + 1),
+        Move::Classical(sq) => format!("{}", sq + 1),
+        Move::Measure(sq) => format!("m {}", sq + 1),
+    };
+    format!(
+        "{} in {} moves with best play, starting with \"{}\"",
+        result, solution.distance, mov
+    )
+}
+
+// runs a game of ultimate quantum tic-tac-toe between two people at the keyboard
+// the sub-boards are played by the normal rules
+fn play_ultimate() {
+    let mut u = ultimate::UltimateBoard::new();
+    let mut input = String::new();
+    let mut has_tried = false;
+    while u.outcome().is_none() {
+        println!("{}", EraseScreen);
+        println!("{}", ultimate::render(&u));
+        if has_tried {
+            print!("Invalid move! ");
+        }
+        let mover = MARKS[u.to_move() as usize];
+        match (u.pending_cycle(), u.target()) {
+            (Some(_), _) => println!(
+                "{} must resolve the cycle! (\"square, move to collapse to\")",
+                mover
+            ),
+            (None, Some(_)) => println!("{}'s move. (\"square1, square2\")", mover),
+            (None, None) => println!(
+                "{}'s move, in any open board. (\"board, square1, square2\")",
+                mover
+            ),
+        }
+        input.clear();
+        std::io::stdin().read_line(&mut input).unwrap();
+        has_tried = true;
+        let nums = all_nums_from_input(&input);
+        if nums.contains(&0) {
+            continue;
+        }
+        let m = match (u.pending_cycle(), u.target(), &nums[..]) {
+            (Some(board), _, &[sq, mov]) => ultimate::UltimateMove {
+                board,
+                mov: Move::Collapse {
+                    sq: sq - 1,
+                    mov: mov - 1,
+                },
+            },
+            (None, Some(board), &[sq1, sq2]) | (None, None, &[board, sq1, sq2]) => {
+                let board = if u.target().is_some() {
+                    board
+                } else {
+                    board - 1
+                };
+                // with one square left in the board it's played classically, naming it twice
+                let mov = if sq1 == sq2 {
+                    Move::Classical(sq1 - 1)
+                } else {
+                    Move::Quantum(sq1 - 1, sq2 - 1)
+                };
+                ultimate::UltimateMove { board, mov }
+            }
+            _ => continue,
+        };
+        if u.is_valid(m) {
+            u.do_move(m);
+            has_tried = false;
+        }
     }
     println!("{}", EraseScreen);
-    println!("{}", render_board(&b).unwrap());
-    match (b.classic().x_wins(), b.classic().o_wins()) {
-        (true, true) | (false, false) => println!("Tie game!"),
-        (true, false) => println!("X wins!"),
-        (false, true) => println!("O wins!"),
+    println!("{}", ultimate::render(&u));
+    match u.outcome() {
+        Some(Outcome::Win(player)) => println!("{} wins!", MARKS[player as usize]),
+        _ => println!("Tie game!"),
+    }
+}
+
+// every number in what was typed, for moves that need more than two
+fn all_nums_from_input(input: &str) -> Vec<u8> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new("[0-9]{1,2}").unwrap();
     }
+    RE.find_iter(input)
+        .map(|m| m.as_str().parse::<u8>().unwrap())
+        .collect()
 }
 
 fn two_num_from_input(input: &str) -> Option<(u8, u8)> {
     lazy_static! {
         static ref RE: Regex = Regex::new("([1-9])[ ,-:_|]*([1-9])").unwrap();
     }
-    match RE.captures(&input) {
-        // unwrap is ok becuase anything that matches the regex should parse correctly
-        Some(cap) => Some((cap[1].parse::<u8>().unwrap(), cap[2].parse::<u8>().unwrap())),
-        None => None,
+    // unwrap is ok becuase anything that matches the regex should parse correctly
+    RE.captures(input)
+        .map(|cap| (cap[1].parse::<u8>().unwrap(), cap[2].parse::<u8>().unwrap()))
+}
+
+// like `two_num_from_input`, but for boards with more than 9 squares the numbers need something between them
+fn nums_from_input(input: &str) -> Option<(u8, u8)> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new("([0-9]{1,2})[ ,:_|-]+([0-9]{1,2})").unwrap();
+    }
+    RE.captures(input)
+        .map(|cap| (cap[1].parse::<u8>().unwrap(), cap[2].parse::<u8>().unwrap()))
+}
+
+// reads a single square number, for the classical move into the last square and measuring
+fn num_from_input(input: &str) -> Option<u8> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new("[0-9]{1,2}").unwrap();
+    }
+    RE.find(input).map(|m| m.as_str().parse::<u8>().unwrap())
+}
+
+// how long each square of a collapse stays on screen before the next one is shown
+const COLLAPSE_STEP_DELAY: Duration = Duration::from_millis(700);
+
+// shows the squares of a collapse becoming classical one at a time, in the order the collapse reached them
+fn animate_collapse<const N: usize>(
+    before: &BoardState<N>,
+    trace: &CollapseTrace,
+    render: &Render<N>,
+) {
+    let steps: Vec<Assignment> = trace.iter().cloned().collect();
+    for shown in 1..=steps.len() {
+        let step = steps[shown - 1];
+        println!("{}", EraseScreen);
+        println!("{}", render(before, &steps[..shown], None));
+        println!(
+            "Collapsing: {}{} is in square {}",
+            MARKS[before.player_of(step.mov) as usize],
+            step.mov + 1,
+            step.sq + 1
+        );
+        std::thread::sleep(COLLAPSE_STEP_DELAY);
     }
 }
 
 use colored::{Color, Colorize};
 use std::fmt::{self, Write};
 // the Result type and `?` throughout this method are just for handling IO errors and can be ignored
-fn render_board(b: &BoardState) -> Result<String, fmt::Error> {
-    fn slice(b: &BoardState, buf: &mut String, sq: u8, row: usize) -> fmt::Result {
+// `collapsed` holds the steps of a collapse in progress to draw on top of the board, the last one is highlighted
+// `heat` adds how often each quantum square is X or O across all the worlds the board could collapse to
+fn render_board(
+    b: &BoardState,
+    collapsed: &[Assignment],
+    heat: Option<&WorldStats>,
+) -> Result<String, fmt::Error> {
+    fn slice(
+        b: &BoardState,
+        collapsed: &[Assignment],
+        buf: &mut String,
+        sq: u8,
+        row: usize,
+    ) -> fmt::Result {
+        // moves already placed by the collapse have their other halves hidden
+        let placed = collapsed.iter().fold(0u32, |mask, a| mask | 1 << a.mov);
         // slice tells us whether its the top, bottom, or middle line of 3 char tall section
-        if b.classic().is_o(sq) {
-            write!(buf, " {} ", O_ASCII[row])?;
-        } else if b.classic().is_x(sq) {
-            write!(buf, " {} ", X_ASCII[row])?;
+        if let Some(pos) = collapsed.iter().position(|a| a.sq == sq) {
+            let ascii = ASCII[b.player_of(collapsed[pos].mov) as usize][row];
+            if pos == collapsed.len() - 1 {
+                write!(buf, " {} ", ascii.black().on_yellow())?;
+            } else {
+                write!(buf, " {} ", ascii)?;
+            }
+        } else if let Some(player) = b.classic().player_at(sq) {
+            write!(buf, " {} ", ASCII[player as usize][row])?;
         } else {
             // render quantum
-            for col in 0..3 {
-                let mov = 3 * row + col;
-                if !b.quantum().is(mov as u8, sq) {
-                    write!(buf, "   ")?;
+            // each of the first 9 moves has its own spot, measuring skips move numbers so there can be
+            // later ones too, and they go in the spots left over (a square never has more than 8 moves)
+            let mut spots = [None; 9];
+            for mov in (0..b.next_mov()).filter(|&mov| b.quantum().is(mov, sq)) {
+                let spot = if mov < 9 {
+                    Some(mov as usize)
                 } else {
-                    // bring the colors into scope for formatting with them
-                    use self::Color::*;
-                    write!(
-                        buf,
-                        " {}",
-                        format!("{}{}", ["X", "O"][mov % 2], mov + 1).color(
-                            [
-                                Red,
-                                Green,
-                                Yellow,
-                                Blue,
-                                Magenta,
-                                Cyan,
-                                White,
-                                BrightRed,
-                                BrightGreen
-                            ][mov]
-                        )
-                    )?;
+                    (0..9).find(|&spot| spots[spot].is_none() && !b.quantum().is(spot as u8, sq))
+                };
+                if let Some(spot) = spot {
+                    spots[spot] = Some(mov as usize);
                 }
             }
+            for col in 0..3 {
+                let mov = match spots[3 * row + col] {
+                    Some(mov) if placed & (1 << mov) == 0 => mov,
+                    _ => {
+                        write!(buf, "   ")?;
+                        continue;
+                    }
+                };
+                // bring the colors into scope for formatting with them
+                use self::Color::*;
+                write!(
+                    buf,
+                    " {}",
+                    format!("{}{}", MARKS[b.player_of(mov as u8) as usize], mov + 1).color(
+                        [
+                            Red,
+                            Green,
+                            Yellow,
+                            Blue,
+                            Magenta,
+                            Cyan,
+                            White,
+                            BrightRed,
+                            BrightGreen
+                        ][mov % 9]
+                    )
+                )?;
+            }
             write!(buf, " ")?;
         }
         Ok(())
     }
+    // writes the line with the square numbers at the top of each row of squares
+    fn labels(
+        b: &BoardState,
+        heat: Option<&WorldStats>,
+        buf: &mut String,
+        big_row: u8,
+    ) -> fmt::Result {
+        for sq in 3 * big_row..3 * big_row + 3 {
+            write!(buf, "{}", sq + 1)?;
+            match heat {
+                Some(stats) if b.quantum().mask_in(sq) > 0 => {
+                    let x = (100.0 * stats.x_fraction(sq)).round();
+                    let o = (100.0 * stats.o_fraction(sq)).round();
+                    let label = format!(" X{:<3}O{:<3}", x, o);
+                    // shaded towards whoever the square is more likely to end up as
+                    if x > o {
+                        write!(buf, "{}", label.on_red())?;
+                    } else if o > x {
+                        write!(buf, "{}", label.on_blue())?;
+                    } else {
+                        write!(buf, "{}", label)?;
+                    }
+                }
+                _ => write!(buf, "         ")?,
+            }
+            write!(buf, "{}", if sq % 3 == 2 { " " } else { "|" })?;
+        }
+        writeln!(buf)
+    }
     let mut buf = String::new();
 
-    writeln!(buf, "1         |2         |3          ")?;
+    labels(b, heat, &mut buf, 0)?;
     for big_row in 0..3 {
         for small_row in 0..3 {
-            slice(b, &mut buf, 3 * big_row + 0, small_row)?;
+            slice(b, collapsed, &mut buf, 3 * big_row, small_row)?;
             write!(buf, "|")?;
-            slice(b, &mut buf, 3 * big_row + 1, small_row)?;
+            slice(b, collapsed, &mut buf, 3 * big_row + 1, small_row)?;
             write!(buf, "|")?;
-            slice(b, &mut buf, 3 * big_row + 2, small_row)?;
-            writeln!(buf, "")?;
+            slice(b, collapsed, &mut buf, 3 * big_row + 2, small_row)?;
+            writeln!(buf)?;
         }
         if big_row < 2 {
             writeln!(buf, "__________|__________|___________")?;
-            writeln!(
-                buf,
-                "{}         |{}         |{}          ",
-                big_row * 3 + 4,
-                big_row * 3 + 5,
-                big_row * 3 + 6
-            )?;
+            labels(b, heat, &mut buf, big_row + 1)?;
         }
     }
 
@@ -140,11 +689,16 @@ fn render_board(b: &BoardState) -> Result<String, fmt::Error> {
     // write the cycle if the board has it
     if b.has_cycle() {
         write!(buf, "Cycle:")?;
-        b.cycle().for_each(|sq| write!(buf, " {}", sq + 1).unwrap());
+        for sq in b.cycle() {
+            write!(buf, " {}", sq + 1)?;
+        }
     }
     Ok(buf)
 }
 
-static X_ASCII: [&str; 3] = [" XX  XX ", "  XXXX  ", " XX  XX "];
-
-static O_ASCII: [&str; 3] = [" OOOOOO ", " OO  OO ", " OOOOOO "];
+// the big version of each player's mark, indexed by player then by line
+static ASCII: [[&str; 3]; MAX_PLAYERS] = [
+    [" XX  XX ", "  XXXX  ", " XX  XX "],
+    [" OOOOOO ", " OO  OO ", " OOOOOO "],
+    [" YY  YY ", "  YYYY  ", "   YY   "],
+];
@@ -0,0 +1,330 @@
+use crate::eval::EVAL_LIMIT;
+use crate::file::{FileError, Layout};
+use crate::search::result;
+use crate::BoardState;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use smallvec::SmallVec;
+use std::path::Path;
+
+// the numbers `encode` gives for each square, and for the position as a whole
+const PER_SQUARE: usize = 5;
+const GLOBAL: usize = 2;
+
+// every weight is a record of its own
+const LAYOUT: Layout = Layout {
+    kind: "network",
+    magic: b"QTNN",
+    version: 1,
+    header: 8,
+    record: 8,
+};
+
+/// The inputs `encode` gives a network, and the result the network should learn for them
+pub type Example = (Encoding, f64);
+
+/// A position turned into numbers for a network, see `encode`
+pub type Encoding = SmallVec<[f64; 64]>;
+
+/// A tiny neural network that evaluates positions: one hidden layer of tanh units, and an output
+/// squashed the same way as `eval::Weights::evaluate`
+///
+/// It only knows boards with the number of squares it was made for, since every square has its own
+/// inputs. `train` fits it to examples with plain stochastic gradient descent, and `save` and
+/// `load` keep it in a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Network {
+    cells: u8,
+    hidden: usize,
+    // `hidden` rows of one weight per input, then a bias for each hidden unit
+    first: Vec<f64>,
+    first_bias: Vec<f64>,
+    // one weight per hidden unit, and the output's bias
+    second: Vec<f64>,
+    second_bias: f64,
+}
+
+// how many inputs a board with `cells` squares has
+pub fn inputs_for(cells: u8) -> usize {
+    PER_SQUARE * cells as usize + GLOBAL
+}
+
+// the inputs for `b`, all from X's point of view:
+// for each square, whether X or O has a classical mark there, how many quantum marks each of them
+// has there, and whether it's in a cycle waiting to collapse,
+// then 1 if it's X's move or -1 if it's O's, and 1 or -1 for which of them picks the collapse
+// only X and O count, so this is for two player games
+pub fn encode<const N: usize>(b: &BoardState<N>) -> Encoding {
+    let cells = b.cells();
+    let mut inputs: Encoding = SmallVec::from_elem(0.0, inputs_for(cells));
+    for sq in 0..cells {
+        let square = &mut inputs[PER_SQUARE * sq as usize..][..PER_SQUARE];
+        match b.classic().player_at(sq) {
+            Some(0) => square[0] = 1.0,
+            Some(1) => square[1] = 1.0,
+            _ => {}
+        }
+        for mov in (0..b.next_mov()).filter(|&mov| b.quantum().is(mov, sq)) {
+            match b.player_of(mov) {
+                0 => square[2] += 1.0,
+                1 => square[3] += 1.0,
+                _ => {}
+            }
+        }
+    }
+    for &sq in b.cycle() {
+        inputs[PER_SQUARE * sq as usize + 4] = 1.0;
+    }
+    let global = PER_SQUARE * cells as usize;
+    inputs[global] = if b.to_move() == 0 { 1.0 } else { -1.0 };
+    inputs[global + 1] = match (b.has_cycle(), b.collapse_chooser()) {
+        (true, Some(0)) => 1.0,
+        (true, Some(_)) => -1.0,
+        _ => 0.0,
+    };
+    inputs
+}
+
+impl Network {
+    // a network for boards with `cells` squares and `hidden` hidden units, with small random weights
+    pub fn new<R: Rng>(cells: u8, hidden: usize, rng: &mut R) -> Self {
+        let inputs = inputs_for(cells);
+        // small enough that the hidden units start out far from saturating
+        let scale = (1.0 / inputs as f64).sqrt();
+        Network {
+            cells,
+            hidden,
+            first: (0..hidden * inputs)
+                .map(|_| rng.gen_range(-scale..scale))
+                .collect(),
+            first_bias: vec![0.0; hidden],
+            second: (0..hidden).map(|_| rng.gen_range(-scale..scale)).collect(),
+            second_bias: 0.0,
+        }
+    }
+
+    // the number of squares on the boards it evaluates
+    pub fn cells(&self) -> u8 {
+        self.cells
+    }
+
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    // the value of `b` from X's point of view, strictly between -1 and 1 unless the game is over,
+    // like `eval::Weights::evaluate`
+    pub fn evaluate<const N: usize>(&self, b: &BoardState<N>) -> f64 {
+        debug_assert_eq!(b.cells(), self.cells);
+        match result(b) {
+            Some(value) => value,
+            None => self.predict(&encode(b)),
+        }
+    }
+
+    // what the network gives for some inputs from `encode`
+    pub fn predict(&self, inputs: &[f64]) -> f64 {
+        let mut activations: SmallVec<[f64; 64]> = SmallVec::new();
+        EVAL_LIMIT * self.forward(inputs, &mut activations).tanh()
+    }
+
+    // the output before it's squashed, leaving the hidden units' values in `activations`
+    fn forward(&self, inputs: &[f64], activations: &mut SmallVec<[f64; 64]>) -> f64 {
+        activations.clear();
+        let rows = self.first.chunks(inputs.len()).zip(&self.first_bias);
+        activations.extend(rows.map(|(row, bias)| {
+            let sum: f64 = row.iter().zip(inputs).map(|(w, x)| w * x).sum();
+            (sum + bias).tanh()
+        }));
+        let out: f64 = self
+            .second
+            .iter()
+            .zip(activations.iter())
+            .map(|(w, a)| w * a)
+            .sum();
+        out + self.second_bias
+    }
+
+    // fits the network to `examples` by going through them in a random order `epochs` times and
+    // nudging every weight against the gradient of the squared error after each one
+    // returns the mean squared error over the examples after the last epoch
+    pub fn train<R: Rng>(
+        &mut self,
+        examples: &[Example],
+        epochs: u32,
+        rate: f64,
+        rng: &mut R,
+    ) -> f64 {
+        let mut order: Vec<usize> = (0..examples.len()).collect();
+        let mut activations = SmallVec::new();
+        for _ in 0..epochs {
+            order.shuffle(rng);
+            for &idx in order.iter() {
+                let (inputs, target) = &examples[idx];
+                let squashed = self.forward(inputs, &mut activations).tanh();
+                // the derivative of the squared error with respect to the output before squashing
+                let delta = 2.0
+                    * (EVAL_LIMIT * squashed - target)
+                    * EVAL_LIMIT
+                    * (1.0 - squashed * squashed);
+                for (unit, &activation) in activations.iter().enumerate() {
+                    // worked out before the second layer's weight changes, since it's used to get here
+                    let hidden_delta = delta * self.second[unit] * (1.0 - activation * activation);
+                    self.second[unit] -= rate * delta * activation;
+                    let row = &mut self.first[unit * inputs.len()..][..inputs.len()];
+                    for (w, x) in row.iter_mut().zip(inputs.iter()) {
+                        *w -= rate * hidden_delta * x;
+                    }
+                    self.first_bias[unit] -= rate * hidden_delta;
+                }
+                self.second_bias -= rate * delta;
+            }
+        }
+        self.error(examples)
+    }
+
+    // the mean squared difference between what the network gives for each example and its result
+    pub fn error(&self, examples: &[Example]) -> f64 {
+        let total: f64 = examples
+            .iter()
+            .map(|(inputs, target)| (self.predict(inputs) - target).powi(2))
+            .sum();
+        total / examples.len().max(1) as f64
+    }
+
+    // writes the network in the format `from_bytes` reads:
+    // "QTNN", a version byte, the number of squares, the number of hidden units as 2 little-endian
+    // bytes, and then every weight as 8 little-endian bytes: the first layer's weights row by row,
+    // its biases, the second layer's weights and its bias
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = LAYOUT.start(self.first.len() + 2 * self.hidden + 1);
+        bytes.push(self.cells);
+        bytes.extend_from_slice(&(self.hidden as u16).to_le_bytes());
+        let weights = self
+            .first
+            .iter()
+            .chain(&self.first_bias)
+            .chain(&self.second);
+        for weight in weights.chain(std::iter::once(&self.second_bias)) {
+            bytes.extend_from_slice(&weight.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        LAYOUT.check(bytes)?;
+        let cells = bytes[5];
+        let hidden = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        let inputs = inputs_for(cells);
+        let records = LAYOUT.records(bytes, hidden * inputs + 2 * hidden + 1)?;
+        let mut weights = records.map(|chunk| {
+            let mut weight = [0; 8];
+            weight.copy_from_slice(chunk);
+            f64::from_le_bytes(weight)
+        });
+        let mut take = |count: usize| weights.by_ref().take(count).collect::<Vec<f64>>();
+        let first = take(hidden * inputs);
+        let first_bias = take(hidden);
+        let second = take(hidden);
+        let second_bias = take(1)[0];
+        Ok(Network {
+            cells,
+            hidden,
+            first,
+            first_bias,
+            second,
+            second_bias,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FileError> {
+        LAYOUT.save(path, self.to_bytes())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FileError> {
+        Self::from_bytes(&LAYOUT.load(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordering::sample_positions;
+    use crate::search::Expectimax;
+    use crate::Move;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::Arc;
+
+    #[test]
+    fn encodings() {
+        let mut b = BoardState::new();
+        let empty = encode(&b);
+        assert_eq!(empty.len(), 47);
+        assert_eq!(empty.iter().sum::<f64>(), 1.0);
+        assert_eq!(empty[45], 1.0);
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(0, 1));
+        let cycle = encode(&b);
+        // X's and O's marks are both in squares 0 and 1, which are in the cycle, and X picks
+        assert_eq!(&cycle[..5], &[0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(&cycle[5..10], &[0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(&cycle[45..], &[1.0, 1.0]);
+        b.do_move(Move::Collapse { sq: 0, mov: 0 });
+        let collapsed = encode(&b);
+        assert_eq!(
+            &collapsed[..10],
+            &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(&collapsed[45..], &[1.0, 0.0]);
+    }
+
+    #[test]
+    fn training_fits_examples() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut network = Network::new(9, 8, &mut rng);
+        // made up results for some real positions, which a network this size can learn exactly
+        let examples: Vec<Example> = sample_positions()
+            .iter()
+            .zip([0.5, -0.5, 0.25, -0.75].iter())
+            .map(|(b, &value)| (encode(b), value))
+            .collect();
+        let before = network.error(&examples);
+        let after = network.train(&examples, 300, 0.05, &mut rng);
+        assert!(after < before / 10.0, "{} {}", before, after);
+        for (b, (_, value)) in sample_positions().iter().zip(examples.iter()) {
+            assert!((network.evaluate(b) - value).abs() < 0.1);
+            assert!(network.evaluate(b).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn searches_with_a_network() {
+        let network = Arc::new(Network::new(9, 8, &mut StdRng::seed_from_u64(5)));
+        let mut b = BoardState::new();
+        let (value, m) = Expectimax::new()
+            .with_evaluation(network.clone())
+            .search(&mut b, 1);
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let best = moves
+            .iter()
+            .map(|&m| {
+                let mut after = b.clone();
+                after.do_move(m);
+                network.evaluate(&after)
+            })
+            .fold(f64::NEG_INFINITY, f64::max);
+        assert_eq!(value, best);
+        assert!(b.is_valid(m.unwrap()));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let network = Network::new(9, 4, &mut StdRng::seed_from_u64(2));
+        let bytes = network.to_bytes();
+        assert_eq!(bytes.len(), 8 + 8 * (4 * 47 + 2 * 4 + 1));
+        assert_eq!(Network::from_bytes(&bytes).unwrap(), network);
+        assert!(Network::from_bytes(&bytes[..100]).is_err());
+        assert!(Network::from_bytes(b"not a network").is_err());
+    }
+}
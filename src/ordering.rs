@@ -0,0 +1,268 @@
+use crate::{BoardState, Move, Topology};
+
+// the parts of a move's score, so a hash move always comes first and killers come before the rest
+const HASH_MOVE: u32 = 1 << 30;
+const KILLER: u32 = 1 << 28;
+const CLOSES_CYCLE: u32 = 1 << 12;
+const LINE: u32 = 1 << 8;
+
+// how many best moves are remembered, a power of two so a key can be masked down to a slot
+const HASH_SLOTS: usize = 1 << 14;
+// quantum moves and collapses each get a table of square pairs in `history`
+const PAIRS: usize = Topology::MAX_CELLS * Topology::MAX_CELLS;
+
+/// Scores moves so the search tries the ones most likely to be best first
+///
+/// Alpha-beta only skips moves once it's found a good one, so the sooner that happens the less
+/// it searches. In order, this puts first the best move found the last time a position was
+/// searched, then the two most recent moves that were good enough to stop a search at the
+/// same ply (killers), then moves that close a cycle, then moves into lines that can still be
+/// won. Ties are broken by how often a move's squares have stopped a search before (history).
+pub struct MoveOrder {
+    // the key of a position and the best move found there
+    hash_moves: Vec<(u64, Option<Move>)>,
+    killers: Vec<[Option<Move>; 2]>,
+    // indexed by `pair`
+    history: Vec<u32>,
+    // scores for the moves being sorted, kept to avoid allocating for every position
+    scored: Vec<(u32, Move)>,
+}
+
+impl MoveOrder {
+    pub fn new() -> Self {
+        MoveOrder {
+            hash_moves: vec![(0, None); HASH_SLOTS],
+            killers: Vec::new(),
+            history: vec![0; 2 * PAIRS],
+            scored: Vec::new(),
+        }
+    }
+
+    // forgets everything, for starting on a position that has nothing to do with the last one
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    // the best move remembered for the position with this key
+    pub fn hash_move(&self, key: u64) -> Option<Move> {
+        match self.hash_moves[key as usize % HASH_SLOTS] {
+            (stored, m) if stored == key => m,
+            _ => None,
+        }
+    }
+
+    // remembers the best move found for the position with this key, replacing whatever was in its slot
+    pub fn remember(&mut self, key: u64, m: Move) {
+        self.hash_moves[key as usize % HASH_SLOTS] = (key, Some(m));
+    }
+
+    // sorts `moves` for `b` best first, `ply` is how far the position is from where the search started
+    pub fn sort<const N: usize>(&mut self, b: &BoardState<N>, moves: &mut [Move], ply: usize) {
+        let hash_move = self.hash_move(b.key());
+        let killers = self.killers.get(ply).copied().unwrap_or_default();
+        let lines = LineCounts::new(b);
+        let mut scored = std::mem::take(&mut self.scored);
+        scored.clear();
+        scored.extend(moves.iter().map(|&m| {
+            let mut score = self.history[pair(b, m)];
+            if Some(m) == hash_move {
+                score += HASH_MOVE;
+            }
+            if killers.contains(&Some(m)) {
+                score += KILLER;
+            }
+            match m {
+                Move::Quantum(sq1, sq2) => {
+                    if entangled(b, sq1, sq2) {
+                        score += CLOSES_CYCLE;
+                    }
+                    score += LINE * (lines.score(sq1) + lines.score(sq2));
+                }
+                Move::Classical(sq) | Move::Measure(sq) => score += LINE * lines.score(sq),
+                Move::Collapse { .. } => {}
+            }
+            (score, m)
+        }));
+        // stable, so moves that score the same stay in the order they were generated in
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        for (m, &(_, scored)) in moves.iter_mut().zip(scored.iter()) {
+            *m = scored;
+        }
+        self.scored = scored;
+    }
+
+    // tells the ordering that `m` was good enough to stop the search of `b`, with `depth` left to go
+    pub fn cutoff<const N: usize>(&mut self, b: &BoardState<N>, m: Move, depth: u32, ply: usize) {
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None, None]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+        // cutoffs with more left to search saved more work, so they count for more
+        let history = &mut self.history[pair(b, m)];
+        *history = history.saturating_add(depth * depth).min(LINE - 1);
+    }
+}
+
+impl Default for MoveOrder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// where a move goes in the history table: the two squares of a quantum move, or the square a
+// collapse puts a mark in and the square it takes it out of
+// classical moves and measurements only have one square, so it's used for both
+fn pair<const N: usize>(b: &BoardState<N>, m: Move) -> usize {
+    let index = |sq1: u8, sq2: u8| sq1 as usize * Topology::MAX_CELLS + sq2 as usize;
+    match m {
+        Move::Quantum(sq1, sq2) => index(sq1, sq2),
+        Move::Classical(sq) | Move::Measure(sq) => index(sq, sq),
+        Move::Collapse { sq, mov } => {
+            let other = (0..b.cells())
+                .find(|&other| other != sq && b.quantum().is(mov, other))
+                .unwrap_or(sq);
+            PAIRS + index(sq, other)
+        }
+    }
+}
+
+// whether two squares are already connected through quantum marks, so a move in both closes a cycle
+pub fn entangled<const N: usize>(b: &BoardState<N>, sq1: u8, sq2: u8) -> bool {
+    let q = b.quantum();
+    // spread out from `sq1` like a flood fill: every square with one of the marks found so far
+    // adds all of its marks, until no new ones turn up
+    let mut marks = q.mask_in(sq1);
+    loop {
+        let reached = (0..b.cells())
+            .map(|sq| q.mask_in(sq))
+            .filter(|&mask| mask & marks > 0)
+            .fold(marks, |reached, mask| reached | mask);
+        if reached == marks {
+            return q.mask_in(sq2) & marks > 0;
+        }
+        marks = reached;
+    }
+}
+
+// how promising each line is for the player to move, worked out once per position
+struct LineCounts {
+    lines: &'static [u32],
+    mine: u32,
+    theirs: u32,
+}
+
+impl LineCounts {
+    fn new<const N: usize>(b: &BoardState<N>) -> Self {
+        let player = b.to_move();
+        let theirs = (0..b.rules().players)
+            .filter(|&other| other != player)
+            .fold(0, |squares, other| squares | b.classic().squares_of(other));
+        LineCounts {
+            lines: b.topology().lines(),
+            mine: b.classic().squares_of(player),
+            theirs,
+        }
+    }
+
+    // the lines through `sq` that nobody else has a mark in yet, worth more the more of them are already ours
+    fn score(&self, sq: u8) -> u32 {
+        self.lines
+            .iter()
+            .filter(|&&line| line & (1 << sq) > 0 && line & self.theirs == 0)
+            .map(|&line| 1 + 2 * (line & self.mine).count_ones())
+            .sum()
+    }
+}
+
+/// A handful of positions from different stages of the game, for measuring searches against each other
+///
+/// They're all on the normal board under the normal rules: the empty board, a few quantum moves
+/// in, a cycle waiting to be collapsed, and a middle game with classical marks on both sides.
+pub fn sample_positions() -> Vec<BoardState> {
+    let games: [&[Move]; 4] = [
+        &[],
+        &[
+            Move::Quantum(0, 4),
+            Move::Quantum(4, 8),
+            Move::Quantum(2, 6),
+        ],
+        &[
+            Move::Quantum(0, 1),
+            Move::Quantum(1, 2),
+            Move::Quantum(4, 5),
+            Move::Quantum(2, 0),
+        ],
+        &[
+            Move::Quantum(0, 1),
+            Move::Quantum(0, 1),
+            Move::Collapse { sq: 0, mov: 0 },
+            Move::Quantum(4, 3),
+            Move::Quantum(4, 3),
+            Move::Collapse { sq: 4, mov: 2 },
+            Move::Quantum(2, 6),
+        ],
+    ];
+    games
+        .iter()
+        .map(|game| {
+            let mut b = BoardState::new();
+            for &m in game.iter() {
+                b.do_move(m);
+            }
+            b
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_and_line_moves_first() {
+        let mut b = BoardState::new();
+        b.do_move(Move::Quantum(0, 1));
+        b.do_move(Move::Quantum(1, 2));
+        assert!(entangled(&b, 0, 2));
+        assert!(!entangled(&b, 0, 3));
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let mut order = MoveOrder::new();
+        order.sort(&b, &mut moves, 0);
+        // the cycle closing moves all join two of 0, 1 and 2
+        let closes = |m: &Move| match *m {
+            Move::Quantum(sq1, sq2) => sq1 <= 2 && sq2 <= 2,
+            _ => false,
+        };
+        assert!(moves[..3].iter().all(closes));
+        assert!(!moves[3..].iter().any(closes));
+        // after those, the center is in the most lines
+        assert!(matches!(
+            moves[3],
+            Move::Quantum(4, _) | Move::Quantum(_, 4)
+        ));
+    }
+
+    #[test]
+    fn remembered_moves_first() {
+        let b = BoardState::new();
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let mut order = MoveOrder::new();
+        order.remember(b.key(), Move::Quantum(5, 7));
+        order.cutoff(&b, Move::Quantum(1, 3), 2, 0);
+        order.sort(&b, &mut moves, 0);
+        assert_eq!(moves[0], Move::Quantum(5, 7));
+        assert_eq!(moves[1], Move::Quantum(1, 3));
+        // killers are per ply, but history isn't
+        order.sort(&b, &mut moves, 1);
+        assert_eq!(moves[1], Move::Quantum(0, 4));
+        assert!(moves.contains(&Move::Quantum(1, 3)));
+        order.clear();
+        assert_eq!(order.hash_move(b.key()), None);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::book::Book;
+use crate::eval::Evaluator;
+use crate::search::{Expectimax, Limits, SearchResult};
+use crate::table::Table;
+use crate::tablebase::Tablebase;
+use crate::BoardState;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Searches with several threads at once, sharing what they find through a `Table`
+///
+/// Every thread runs its own iterative deepening search of the whole position (lazy SMP). Half of
+/// the helper threads start a depth ahead of the main thread, so they usually reach positions
+/// first and leave results in the table for it to skip. The main thread's result is the one
+/// returned, and the helpers stop as soon as it's done.
+///
+/// With an opening book, positions in it are played straight from the book without searching.
+///
+/// With one thread there are no helpers and the search runs on the calling thread, so its
+/// results are the same every time.
+pub struct ParallelSearch {
+    threads: usize,
+    // `None` for a single thread, since then no pool is needed
+    pool: Option<ThreadPool>,
+    table: Arc<Table>,
+    tablebase: Option<Arc<Tablebase>>,
+    book: Option<Arc<Book>>,
+    evaluator: Option<Evaluator>,
+}
+
+// room for about a million positions, which takes 24MB
+const DEFAULT_TABLE_SLOTS: usize = 1 << 20;
+
+impl ParallelSearch {
+    // a search on `threads` threads, 0 for one on each core
+    pub fn new(threads: usize) -> Self {
+        let threads = match threads {
+            0 => std::thread::available_parallelism().map_or(1, |cores| cores.get()),
+            threads => threads,
+        };
+        let pool = if threads > 1 {
+            Some(
+                ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("couldn't start the search threads"),
+            )
+        } else {
+            None
+        };
+        ParallelSearch {
+            threads,
+            pool,
+            table: Arc::new(Table::new(DEFAULT_TABLE_SLOTS)),
+            tablebase: None,
+            book: None,
+            evaluator: None,
+        }
+    }
+
+    // every thread stops at positions in `tablebase`, see `Expectimax::with_tablebase`
+    pub fn with_tablebase(mut self, tablebase: Arc<Tablebase>) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    // plays the best move in `book` instead of searching, for positions that are in it
+    pub fn with_book(mut self, book: Arc<Book>) -> Self {
+        self.book = Some(book);
+        self
+    }
+
+    // every thread evaluates positions the depth runs out at, see `Expectimax::with_evaluation`
+    pub fn with_evaluation(mut self, evaluator: impl Into<Evaluator>) -> Self {
+        self.evaluator = Some(evaluator.into());
+        self
+    }
+
+    // a search for one of the threads, sharing the table, tablebase and evaluation
+    fn thread_search(&self) -> Expectimax {
+        let mut search = Expectimax::new().with_table(self.table.clone());
+        if let Some(tablebase) = &self.tablebase {
+            search = search.with_tablebase(tablebase.clone());
+        }
+        if let Some(evaluator) = &self.evaluator {
+            search = search.with_evaluation(evaluator.clone());
+        }
+        search
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn table(&self) -> &Arc<Table> {
+        &self.table
+    }
+
+    // searches `b` until the main thread hits one of `limits`, like `Expectimax::search_with_limits`
+    // the nodes in the result are counted across every thread
+    // the table is kept between searches, so it should be cleared before searching another game
+    pub fn search<const N: usize>(&self, b: &BoardState<N>, limits: Limits) -> SearchResult {
+        if let Some(found) = self.book.as_ref().and_then(|book| book.best_move(b)) {
+            return SearchResult {
+                value: found.value,
+                best: Some(found.m),
+                depth: 0,
+                nodes: 0,
+                solved: false,
+                book: true,
+            };
+        }
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => {
+                let mut search = self.thread_search();
+                return search.search_with_limits(&mut b.clone(), limits);
+            }
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let results = pool.broadcast(|thread| {
+            let mut search = self
+                .thread_search()
+                .starting_at_depth(1 + thread.index() as u32 % 2);
+            if thread.index() == 0 {
+                let result = search.search_with_limits(&mut b.clone(), limits);
+                stop.store(true, Ordering::Relaxed);
+                result
+            } else {
+                // helpers only stop when the main thread does
+                let helper_limits = Limits {
+                    nodes: None,
+                    ..limits
+                };
+                search
+                    .with_stop(stop.clone())
+                    .search_with_limits(&mut b.clone(), helper_limits)
+            }
+        });
+        SearchResult {
+            nodes: results.iter().map(|result| result.nodes).sum(),
+            ..results[0]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordering::sample_positions;
+    use crate::{Move, RuleSet};
+
+    #[test]
+    fn single_thread_repeats() {
+        let limits = Limits {
+            depth: Some(4),
+            ..Limits::default()
+        };
+        for b in sample_positions() {
+            let first = ParallelSearch::new(1).search(&b, limits);
+            let second = ParallelSearch::new(1).search(&b, limits);
+            assert_eq!(first, second);
+            // the table doesn't change the value, only how much is searched to find it
+            let (value, _) = Expectimax::new().search(&mut b.clone(), 4);
+            assert_eq!(first.value, value);
+        }
+    }
+
+    #[test]
+    fn book_moves_skip_the_search() {
+        let mut book = Book::new(RuleSet::default());
+        let b = BoardState::new();
+        book.record(&b, Move::Quantum(2, 4), 0.25);
+        let engine = ParallelSearch::new(1).with_book(Arc::new(book));
+        let limits = Limits {
+            depth: Some(2),
+            ..Limits::default()
+        };
+        let found = engine.search(&b, limits);
+        assert_eq!(found.best, Some(Move::Quantum(2, 4)));
+        assert_eq!((found.value, found.nodes, found.book), (0.25, 0, true));
+        // out of the book, it searches as usual
+        let mut after = b.clone();
+        after.do_move(Move::Quantum(2, 4));
+        let found = engine.search(&after, limits);
+        assert!(!found.book && found.nodes > 0 && found.depth == 2);
+    }
+
+    #[test]
+    fn threads_agree() {
+        let limits = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        for b in sample_positions() {
+            let (value, _) = Expectimax::new().search(&mut b.clone(), 3);
+            // one thread runs on its own, the same as a single search
+            let found = ParallelSearch::new(1).search(&b, limits);
+            assert_eq!((found.depth, found.value), (3, value));
+            // helpers keep the depth limit, so nothing they leave in the table is deeper than 3
+            let found = ParallelSearch::new(3).search(&b, limits);
+            assert_eq!((found.depth, found.value), (3, value));
+            assert!(found.best.is_none_or(|m| b.is_valid(m)));
+        }
+    }
+}
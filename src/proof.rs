@@ -0,0 +1,492 @@
+use crate::search::Limits;
+use crate::{BoardState, Move, Outcome};
+use std::time::Instant;
+
+// a proof or disproof number this big means the node can't be proven or disproven at all
+const INFINITE: u32 = u32::MAX;
+
+/// What a collapse that gives both players a row counts as when proving a win
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Simultaneous {
+    // a tie, like `RuleSet::outcome` has it, so it's no good for a proof
+    #[default]
+    Tie,
+    // a win for the player the proof is for, like the published scoring that gives both players
+    // points, so a proof can go through positions where they only share the win
+    // under misère a row loses, so sharing one is never a win and this is the same as `Tie`
+    Win,
+}
+
+/// Whether the player to move can force a win, as far as a proof-number search got
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    // they win whatever the other player picks and however measurements turn out
+    Proven,
+    // the other player can always stop them winning, or a measurement can
+    Disproven,
+    // the search ran out of nodes or time before it could tell
+    Unknown,
+}
+
+/// One step along a line of the proof tree
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    // a move the player whose turn it was picked, including measuring a square
+    Move(Move),
+    // a measurement finding `mov` in `sq`, either a cycle collapsing by measurement or the result
+    // of a `Move::Measure` the step before
+    Found { sq: u8, mov: u8 },
+}
+
+/// What a proof-number search found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Proof {
+    pub verdict: Verdict,
+    // the line the answer turns on, with the player to move hurrying and the other player stalling
+    // if it's proven, and the other way around if it's disproven
+    // if the search didn't finish it's the line it would have looked at next
+    pub line: Vec<Step>,
+    // positions put in the tree, and how many of them had their moves generated
+    pub nodes: u64,
+    pub expanded: u64,
+    // how many more positions at least would have to be won or not won to settle the question,
+    // 0 for the one that was settled and `u32::MAX` for the other
+    pub proof_number: u32,
+    pub disproof_number: u32,
+}
+
+/// Proof-number search for whether the player to move can force a win
+///
+/// This is for two player games. The search grows a tree of positions, always expanding the one that
+/// would do the most to settle the question: positions where the player the proof is for picks need
+/// only one winning child, while positions where the other player picks, and cycles and squares that
+/// are measured, need every child to win, since a proof can't count on luck. Each position keeps
+/// how many unsettled positions below it still have to be won (its proof number) or not won (its
+/// disproof number), and the search follows the smallest of them down. Collapses are picked by
+/// whoever the rules say, so the two kinds of position can come in any order.
+///
+/// Ties aren't wins, and neither are rows made by both players in one collapse unless the search is
+/// told otherwise with `with_simultaneous`. Positions reached in more than one order are searched
+/// separately, which keeps the tree a tree so proofs are easy to follow.
+pub struct ProofSearch {
+    simultaneous: Simultaneous,
+    // the tree, with the root first and the children of each position next to each other
+    tree: Vec<Node>,
+    expanded: u64,
+}
+
+// a position in the tree, with how it was reached from its parent instead of the whole board
+struct Node {
+    // `None` for the root
+    step: Option<Step>,
+    parent: u32,
+    // the children are `first..first + count`, all made when the node is expanded
+    first: u32,
+    count: u32,
+    proof: u32,
+    disproof: u32,
+    // whether the player the proof is for picks the child here, otherwise every child has to win
+    or: bool,
+}
+
+impl ProofSearch {
+    pub fn new() -> Self {
+        ProofSearch {
+            simultaneous: Simultaneous::default(),
+            tree: Vec::new(),
+            expanded: 0,
+        }
+    }
+
+    // how rows made by both players in one collapse count, a tie by default
+    pub fn with_simultaneous(mut self, simultaneous: Simultaneous) -> Self {
+        self.simultaneous = simultaneous;
+        self
+    }
+
+    // tries to prove that whoever is to move in `b` can force a win, stopping early if `limits` has
+    // a node count or time that runs out, the depth isn't used
+    pub fn prove<const N: usize>(&mut self, b: &BoardState<N>, limits: Limits) -> Proof {
+        assert_eq!(b.rules().players, 2, "proofs are only for two player games");
+        let start = Instant::now();
+        let attacker = b.to_move();
+        self.tree.clear();
+        self.expanded = 0;
+        self.tree.push(self.node(None, 0, b, attacker));
+
+        while !self.settled(0) {
+            if limits
+                .nodes
+                .is_some_and(|limit| self.tree.len() as u64 >= limit)
+                || limits.time.is_some_and(|time| start.elapsed() >= time)
+            {
+                break;
+            }
+            // down to the most proving position, playing the way there on a copy of the board
+            let mut at = b.clone();
+            let mut idx = 0;
+            while self.tree[idx].count > 0 {
+                idx = self.most_proving_child(idx);
+                apply(
+                    &mut at,
+                    self.tree[idx].step.expect("only the root has no step"),
+                );
+            }
+            self.expand(idx, &at, attacker);
+            self.update(idx);
+        }
+
+        let root = &self.tree[0];
+        let verdict = match (root.proof, root.disproof) {
+            (0, _) => Verdict::Proven,
+            (_, 0) => Verdict::Disproven,
+            _ => Verdict::Unknown,
+        };
+        Proof {
+            verdict,
+            line: self.line(verdict),
+            nodes: self.tree.len() as u64,
+            expanded: self.expanded,
+            proof_number: root.proof,
+            disproof_number: root.disproof,
+        }
+    }
+
+    fn settled(&self, idx: usize) -> bool {
+        self.tree[idx].proof == 0 || self.tree[idx].disproof == 0
+    }
+
+    // a node for the position `b` reached by `step`, settled straight away if the game is over
+    fn node<const N: usize>(
+        &self,
+        step: Option<Step>,
+        parent: u32,
+        b: &BoardState<N>,
+        attacker: u8,
+    ) -> Node {
+        let (proof, disproof, or) = match b.outcome() {
+            Some(outcome) => {
+                let won = match outcome {
+                    Outcome::Win(player) => player == attacker,
+                    Outcome::Loss(player) => player != attacker,
+                    Outcome::Tie => {
+                        self.simultaneous == Simultaneous::Win
+                            && !b.rules().misere
+                            && b.classic().wins(0)
+                            && b.classic().wins(1)
+                    }
+                };
+                if won {
+                    (0, INFINITE, false)
+                } else {
+                    (INFINITE, 0, false)
+                }
+            }
+            // a measured square and a cycle that collapses by measurement are both chance nodes,
+            // and `to_move` would say whoever moves after the measurement
+            None => {
+                let measuring = matches!(step, Some(Step::Move(Move::Measure(_))));
+                let chance = measuring || b.collapse_chances().is_some();
+                (1, 1, !chance && b.to_move() == attacker)
+            }
+        };
+        Node {
+            step,
+            parent,
+            first: 0,
+            count: 0,
+            proof,
+            disproof,
+            or,
+        }
+    }
+
+    // adds the children of `idx`, whose position is `b`
+    fn expand<const N: usize>(&mut self, idx: usize, b: &BoardState<N>, attacker: u8) {
+        self.expanded += 1;
+        let steps: Vec<Step> = match self.tree[idx].step {
+            Some(Step::Move(Move::Measure(sq))) => b
+                .measure_chances(sq)
+                .iter()
+                .filter(|&&(_, p)| p > 0.0)
+                .map(|&(mov, _)| Step::Found { sq, mov })
+                .collect(),
+            _ => match b.collapse_chances() {
+                // a weight of 0 or 100 leaves only one way for the cycle to go
+                Some(chances) => chances
+                    .iter()
+                    .filter(|&&(_, p)| p > 0.0)
+                    .filter_map(|&(m, _)| match m {
+                        Move::Collapse { sq, mov } => Some(Step::Found { sq, mov }),
+                        _ => None,
+                    })
+                    .collect(),
+                None => {
+                    let mut moves = Vec::new();
+                    b.valid_moves(&mut moves);
+                    moves.into_iter().map(Step::Move).collect()
+                }
+            },
+        };
+        // a game that isn't over always has a move, but if it somehow didn't it can't be won
+        if steps.is_empty() {
+            self.tree[idx].proof = INFINITE;
+            self.tree[idx].disproof = 0;
+            return;
+        }
+        let first = self.tree.len() as u32;
+        for step in steps {
+            let mut after = b.clone();
+            apply(&mut after, step);
+            let child = self.node(Some(step), idx as u32, &after, attacker);
+            self.tree.push(child);
+        }
+        let count = self.tree.len() as u32 - first;
+        let node = &mut self.tree[idx];
+        node.first = first;
+        node.count = count;
+    }
+
+    fn children(&self, idx: usize) -> std::ops::Range<usize> {
+        let node = &self.tree[idx];
+        node.first as usize..(node.first + node.count) as usize
+    }
+
+    // the child with the smallest proof number where the player the proof is for picks, and with the
+    // smallest disproof number where they don't, the first one if there's a tie
+    fn most_proving_child(&self, idx: usize) -> usize {
+        let or = self.tree[idx].or;
+        self.children(idx)
+            .min_by_key(|&child| {
+                let child = &self.tree[child];
+                if or {
+                    child.proof
+                } else {
+                    child.disproof
+                }
+            })
+            .expect("only expanded nodes are looked in")
+    }
+
+    // works out the numbers of `idx` and the nodes above it again after it was expanded
+    // it stops once a node's numbers stay the same, since nothing above it can change either, but
+    // always goes past `idx` itself, whose numbers may have been settled by `expand`
+    fn update(&mut self, mut idx: usize) {
+        let mut expanded = true;
+        loop {
+            let node = &self.tree[idx];
+            let (proof, disproof) = if node.count == 0 {
+                (node.proof, node.disproof)
+            } else {
+                let children = &self.tree[self.children(idx)];
+                let min = |number: fn(&Node) -> u32| children.iter().map(number).min();
+                let sum = |number: fn(&Node) -> u32| {
+                    children
+                        .iter()
+                        .map(number)
+                        .fold(0, |sum: u32, n| sum.saturating_add(n))
+                };
+                if node.or {
+                    (min(|n| n.proof).unwrap_or(INFINITE), sum(|n| n.disproof))
+                } else {
+                    (sum(|n| n.proof), min(|n| n.disproof).unwrap_or(INFINITE))
+                }
+            };
+            let node = &mut self.tree[idx];
+            let changed = (node.proof, node.disproof) != (proof, disproof);
+            node.proof = proof;
+            node.disproof = disproof;
+            if idx == 0 || !(changed || expanded) {
+                break;
+            }
+            expanded = false;
+            idx = node.parent as usize;
+        }
+    }
+
+    // the line from the root that `Proof::line` describes
+    fn line(&self, verdict: Verdict) -> Vec<Step> {
+        let picks = match verdict {
+            Verdict::Unknown => Vec::new(),
+            settled => self.picks(settled == Verdict::Proven),
+        };
+        let mut line = Vec::new();
+        let mut idx = 0;
+        while self.tree[idx].count > 0 {
+            idx = match verdict {
+                Verdict::Unknown => self.most_proving_child(idx),
+                _ => picks[idx].expect("a settled node has a settled child").1,
+            };
+            line.push(self.tree[idx].step.expect("only the root has no step"));
+        }
+        line
+    }
+
+    // for every node that's settled the same way as the root, how many steps its line takes and the child
+    // it goes through: the shortest way to settle it for the side that picks it, and the longest the
+    // other side can drag it out for
+    // children always come after their parent in the tree, so going through it backwards has every
+    // child's length ready before its parent needs it
+    fn picks(&self, proven: bool) -> Vec<Option<(u32, usize)>> {
+        let mut picks = vec![None; self.tree.len()];
+        for idx in (0..self.tree.len()).rev() {
+            let node = &self.tree[idx];
+            let settled = if proven {
+                node.proof == 0
+            } else {
+                node.disproof == 0
+            };
+            if !settled {
+                continue;
+            }
+            // a settled leaf ends the line, so it has nowhere to go
+            if node.count == 0 {
+                picks[idx] = Some((0, idx));
+                continue;
+            }
+            let lengths = self
+                .children(idx)
+                .filter_map(|child| picks[child].map(|(length, _)| (length, child)));
+            let found = if node.or == proven {
+                lengths.min_by_key(|&(length, _)| length)
+            } else {
+                lengths.max_by_key(|&(length, _)| length)
+            };
+            picks[idx] = found.map(|(length, child)| (length + 1, child));
+        }
+        picks
+    }
+}
+
+impl Default for ProofSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// plays `step` on `b`, a measured square is left for the `Step::Found` after it
+fn apply<const N: usize>(b: &mut BoardState<N>, step: Step) {
+    match step {
+        Step::Move(Move::Measure(_)) => {}
+        Step::Move(m) => b.do_move(m),
+        // squares can't be measured while there's a cycle, so a cycle means this is its collapse
+        Step::Found { sq, mov } if b.has_cycle() => b.do_move(Move::Collapse { sq, mov }),
+        Step::Found { sq, mov } => {
+            let result = b.measure(sq, mov);
+            debug_assert!(result.is_ok(), "{:?}", result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Expectimax;
+    use crate::selfplay::random_opening;
+    use crate::{ClassicalBoardState, CollapseChooser, Measuring, RuleSet};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn agrees_with_a_full_search() {
+        let mut rng = StdRng::seed_from_u64(6);
+        for &rules in [
+            RuleSet::default(),
+            RuleSet {
+                collapse_chooser: CollapseChooser::Measurement { weight: 30 },
+                ..RuleSet::default()
+            },
+            RuleSet {
+                collapse_chooser: CollapseChooser::Creator,
+                measuring: Measuring::Random,
+                ..RuleSet::default()
+            },
+        ]
+        .iter()
+        {
+            let mut proven = 0;
+            for _ in 0..30 {
+                let mut b = random_opening(rules, 6, &mut rng);
+                if b.classic().game_is_over() {
+                    continue;
+                }
+                let proof = ProofSearch::new().prove(&b, Limits::default());
+                // a forced win is worth exactly a win to a search that averages over measurements
+                let sign = if b.to_move() == 0 { 1.0 } else { -1.0 };
+                let (value, _) = Expectimax::new().search(&mut b, 20);
+                let wins = sign * value > 1.0 - 1e-9;
+                assert_eq!(proof.verdict == Verdict::Proven, wins, "{:?} {}", b, value);
+                assert_ne!(proof.verdict, Verdict::Unknown);
+                assert!(proof.expanded > 0 && proof.nodes > proof.expanded);
+                if wins {
+                    proven += 1;
+                    assert_eq!((proof.proof_number, proof.disproof_number), (0, INFINITE));
+                    // the line ends with the game won
+                    for &step in proof.line.iter() {
+                        apply(&mut b, step);
+                    }
+                    let winner = if sign > 0.0 { 0 } else { 1 };
+                    assert_eq!(b.outcome(), Some(Outcome::Win(winner)));
+                }
+            }
+            assert!(proven > 0);
+        }
+    }
+
+    // X in 0 and 1, O in 3 and 4, and X4 and O5 both in squares 2 and 5, with X picking the collapse
+    // X4 in 2 gives X 0-1-2 and O 3-4-5 at once, X4 in 5 gives nobody a row
+    fn both_rows() -> BoardState {
+        let mut c = ClassicalBoardState::new();
+        c.set_x(0);
+        c.set_x(1);
+        c.set_o(3);
+        c.set_o(4);
+        BoardState::from_marks(c, &[(4, 2, 5), (5, 2, 5)], 6)
+    }
+
+    #[test]
+    fn simultaneous_rows() {
+        let b = both_rows();
+        assert_eq!(b.to_move(), 0);
+        let shared = ProofSearch::new()
+            .with_simultaneous(Simultaneous::Win)
+            .prove(&b, Limits::default());
+        assert_eq!(shared.verdict, Verdict::Proven);
+        assert_eq!(
+            shared.line,
+            vec![Step::Move(Move::Collapse { sq: 2, mov: 4 })]
+        );
+        let tie = ProofSearch::new().prove(&b, Limits::default());
+        assert_eq!(tie.verdict, Verdict::Disproven);
+        assert!(tie.nodes > shared.nodes);
+
+        // under misère both rows lose, so counting them as a win doesn't change anything
+        let b = both_rows().with_rules(RuleSet {
+            misere: true,
+            ..RuleSet::default()
+        });
+        let shared = ProofSearch::new()
+            .with_simultaneous(Simultaneous::Win)
+            .prove(&b, Limits::default());
+        let tie = ProofSearch::new().prove(&b, Limits::default());
+        assert_eq!(shared, tie);
+        assert_ne!(
+            shared.line.first(),
+            Some(&Step::Move(Move::Collapse { sq: 2, mov: 4 }))
+        );
+    }
+
+    #[test]
+    fn limits_stop_the_search() {
+        let proof = ProofSearch::new().prove(
+            &BoardState::new(),
+            Limits {
+                nodes: Some(1000),
+                ..Limits::default()
+            },
+        );
+        assert_eq!(proof.verdict, Verdict::Unknown);
+        assert!(proof.nodes >= 1000 && proof.nodes < 1000 + 36);
+        assert!(proof.proof_number > 0 && proof.disproof_number > 0);
+        assert!(!proof.line.is_empty());
+    }
+}
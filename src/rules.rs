@@ -0,0 +1,184 @@
+use crate::ClassicalBoardState;
+
+// the most players a game can have, since a classical square has two bits to say whose mark it is
+pub const MAX_PLAYERS: usize = 3;
+
+// the mark each player puts down, indexed by player
+pub const MARKS: [char; MAX_PLAYERS] = ['X', 'O', 'Y'];
+
+/// Rules that differ between published versions of the game
+///
+/// `RuleSet::default()` is the game as described in instructions.txt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RuleSet {
+    // how many players take turns, move `mov` belongs to player `mov % players`
+    pub players: u8,
+    pub collapse_chooser: CollapseChooser,
+    pub measuring: Measuring,
+    // completing a row loses instead of winning
+    pub misere: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            players: 2,
+            collapse_chooser: CollapseChooser::default(),
+            measuring: Measuring::default(),
+            misere: false,
+        }
+    }
+}
+
+impl RuleSet {
+    // the player who owns move number `mov`, 0 for X, 1 for O and so on
+    pub fn player_of(&self, mov: u8) -> u8 {
+        mov % self.players
+    }
+
+    // who won a finished game under these rules, `None` if it isn't over yet
+    pub fn outcome(&self, c: &ClassicalBoardState) -> Option<Outcome> {
+        if !c.game_is_over() {
+            return None;
+        }
+        let mut rows = (0..self.players).filter(|&player| c.wins(player));
+        Some(match (rows.next(), rows.next(), self.misere) {
+            (Some(player), None, false) => Outcome::Win(player),
+            // with two players, the one who didn't make a row wins
+            (Some(player), None, true) if self.players == 2 => Outcome::Win(1 - player),
+            (Some(player), None, true) => Outcome::Loss(player),
+            // more than one player getting a row in one collapse is a tie, same as nobody getting one
+            _ => Outcome::Tie,
+        })
+    }
+
+    // the rules of a two player game as four bytes for saving in files: the collapse rule (0 for the
+    // opponent picking, 1 for the creator, 2 for measurement), its measurement weight, the measuring
+    // rule (0 for off, 1 for oldest, 2 for random) and whether it's misère
+    pub(crate) fn to_bytes(self) -> [u8; 4] {
+        let (collapse, weight) = match self.collapse_chooser {
+            CollapseChooser::Opponent => (0, 0),
+            CollapseChooser::Creator => (1, 0),
+            CollapseChooser::Measurement { weight } => (2, weight),
+        };
+        let measuring = match self.measuring {
+            Measuring::Off => 0,
+            Measuring::Oldest => 1,
+            Measuring::Random => 2,
+        };
+        [collapse, weight, measuring, self.misere as u8]
+    }
+
+    // reads the rules back from `to_bytes`, or says what's wrong with them
+    pub(crate) fn from_bytes(bytes: [u8; 4]) -> Result<Self, &'static str> {
+        Ok(RuleSet {
+            collapse_chooser: match bytes[0] {
+                0 => CollapseChooser::Opponent,
+                1 => CollapseChooser::Creator,
+                2 => CollapseChooser::Measurement { weight: bytes[1] },
+                _ => return Err("unknown collapse rule"),
+            },
+            measuring: match bytes[2] {
+                0 => Measuring::Off,
+                1 => Measuring::Oldest,
+                2 => Measuring::Random,
+                _ => return Err("unknown measuring rule"),
+            },
+            misere: match bytes[3] {
+                0 => false,
+                1 => true,
+                _ => return Err("unknown misère setting"),
+            },
+            ..RuleSet::default()
+        })
+    }
+}
+
+/// How a game ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    // the player who won, 0 for X and 1 for O
+    Win(u8),
+    // the player who lost while everyone else shares the win, in misère games with more than two players
+    Loss(u8),
+    Tie,
+}
+
+/// Who gets to pick how a cycle collapses
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CollapseChooser {
+    // the player after the one who made the move that closed the cycle, then they move next as usual
+    #[default]
+    Opponent,
+    // the player who closed the cycle resolves it, then their opponent moves
+    Creator,
+    // nobody chooses, the cycle collapses at random like a measurement would
+    // `weight` is the percent chance that the mark which closed the cycle ends up in the lower numbered
+    // of its two squares, so 50 is a fair coin. The opponent of whoever closed the cycle moves next
+    Measurement {
+        weight: u8,
+    },
+}
+
+/// Whether players can spend a turn measuring a square, and what the measurement finds
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Measuring {
+    // only quantum moves and collapses, like the original game
+    #[default]
+    Off,
+    // a measured square always gets the oldest mark in it
+    Oldest,
+    // each mark in the square is as likely as the others
+    Random,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let normal = RuleSet::default();
+        let misere = RuleSet {
+            misere: true,
+            ..RuleSet::default()
+        };
+        let mut c = ClassicalBoardState::new();
+        c.set_x(0);
+        c.set_x(1);
+        c.set_o(3);
+        assert_eq!(normal.outcome(&c), None);
+        assert_eq!(misere.outcome(&c), None);
+        c.set_x(2);
+        assert_eq!(normal.outcome(&c), Some(Outcome::Win(0)));
+        assert_eq!(misere.outcome(&c), Some(Outcome::Win(1)));
+        // a row each is still a tie
+        c.set_o(4);
+        c.set_o(5);
+        assert_eq!(normal.outcome(&c), Some(Outcome::Tie));
+        assert_eq!(misere.outcome(&c), Some(Outcome::Tie));
+    }
+
+    #[test]
+    fn three_player_outcomes() {
+        let rules = RuleSet {
+            players: 3,
+            ..RuleSet::default()
+        };
+        let misere = RuleSet {
+            misere: true,
+            ..rules
+        };
+        let mut c = ClassicalBoardState::with_topology(crate::Topology::grid(4, 3).leak());
+        for sq in 0..3 {
+            c.set(sq, 2);
+            c.set(sq + 4, 0);
+        }
+        assert!(c.wins(0) && c.wins(2) && !c.wins(1));
+        assert_eq!(rules.outcome(&c), Some(Outcome::Tie));
+        c.clear(4);
+        assert_eq!(rules.outcome(&c), Some(Outcome::Win(2)));
+        assert_eq!(misere.outcome(&c), Some(Outcome::Loss(2)));
+        assert_eq!(rules.player_of(5), 2);
+    }
+}
@@ -0,0 +1,688 @@
+use crate::eval::Evaluator;
+use crate::ordering::MoveOrder;
+use crate::table::{Bound, Entry, Table};
+use crate::tablebase::Tablebase;
+use crate::{BoardState, Move, Outcome};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// how good a finished board is for X under its rules: 1 for an X win, -1 for an O win, 0 for a tie
+// `None` if the game isn't over yet
+pub fn result<const N: usize>(b: &BoardState<N>) -> Option<f64> {
+    b.outcome().map(|outcome| match outcome {
+        Outcome::Win(0) | Outcome::Loss(1) => 1.0,
+        Outcome::Win(_) | Outcome::Loss(_) => -1.0,
+        Outcome::Tie => 0.0,
+    })
+}
+
+/// How long a search is allowed to go on for, any limit left as `None` doesn't apply
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+/// What an iterative deepening search found
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    // the value and best move from the deepest search that finished, see `Expectimax::search`
+    pub value: f64,
+    pub best: Option<Move>,
+    // how deep that search went, 0 if not even the first one finished
+    pub depth: u32,
+    // positions visited across every depth, including the one that was given up on
+    pub nodes: u64,
+    // the whole game tree was searched, so the value is exact
+    pub solved: bool,
+    // the move came from an opening book without searching, and the value is the book's score
+    pub book: bool,
+}
+
+/// Time left on a player's clock, for working out how long to spend on each move
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clock {
+    pub remaining: Duration,
+    // added to the clock after every move
+    pub increment: Duration,
+}
+
+impl Clock {
+    // how long to think about the next move on `b`
+    // the time left is shared out over the moves the player probably still has to make, which is about
+    // one for every two squares left in play, and most of the increment is used as well
+    pub fn allocate<const N: usize>(&self, b: &BoardState<N>) -> Duration {
+        let open = (0..b.cells())
+            .filter(|&sq| b.classic().is_empty(sq))
+            .count() as u32;
+        let moves_left = (open / 2).max(1);
+        let share = self.remaining / moves_left + self.increment * 3 / 4;
+        // never risk more than half of what's left, in case the game goes on longer than expected
+        share.min(self.remaining / 2)
+    }
+}
+
+/// Expectimax search to a fixed depth
+///
+/// This is for two player games. Values are from X's point of view, between -1 and 1: X picks
+/// the highest, O the lowest, and cycles that collapse by measurement and measured squares are
+/// averaged over their outcomes. Positions that aren't over when the depth runs out are worth 0,
+/// or their static evaluation when the search has an `Evaluator`, which always stays between the
+/// values of a win and a loss. Measurements are forced, so they don't use up depth.
+///
+/// Moves are pruned with alpha-beta once a player has a move the other won't allow, which works
+/// best when the good moves are tried first, see `MoveOrder`. Chance nodes can't be pruned, since
+/// every outcome counts towards the average. Given a `Table`, it also skips positions that have
+/// already been searched deep enough, and given a `Tablebase` it stops at positions that are in it.
+pub struct Expectimax {
+    // positions visited by the last search, for comparing searches
+    pub nodes: u64,
+    // how many positions stopped early because a move was too good, and how many of those stopped on
+    // the first move tried, which is what good move ordering is for
+    pub cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    // whether moves are sorted before they're searched, on by default
+    ordering: bool,
+    order: MoveOrder,
+    // positions searched before, possibly by other threads
+    table: Option<Arc<Table>>,
+    tablebase: Option<Arc<Tablebase>>,
+    // for valuing positions the depth runs out at
+    evaluator: Option<Evaluator>,
+    // set by another thread to stop this search, see `ParallelSearch`
+    stop: Option<Arc<AtomicBool>>,
+    // the depth iterative deepening starts from, so helper threads can get ahead of the main one
+    first_depth: u32,
+    // one buffer of moves per ply, so the search doesn't allocate once they've grown
+    moves: Vec<Vec<Move>>,
+    // the node count and time to give up at, if the search has them
+    node_limit: Option<u64>,
+    deadline: Option<Instant>,
+    // set once a limit is hit, after which every node returns straight away
+    aborted: bool,
+    // whether any position was valued at 0 because the depth ran out
+    cut_off: bool,
+}
+
+// how many nodes go by between looking at the time, since that's slower than the rest of a node
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+impl Expectimax {
+    pub fn new() -> Self {
+        Expectimax {
+            nodes: 0,
+            cutoffs: 0,
+            first_move_cutoffs: 0,
+            ordering: true,
+            order: MoveOrder::new(),
+            table: None,
+            tablebase: None,
+            evaluator: None,
+            stop: None,
+            first_depth: 1,
+            moves: Vec::new(),
+            node_limit: None,
+            deadline: None,
+            aborted: false,
+            cut_off: false,
+        }
+    }
+
+    // searches the moves in the order `valid_moves` gives them instead, to see how much the ordering helps
+    // the best moves it remembers carry over between searches, so `false` also stops that
+    pub fn with_ordering(mut self, ordering: bool) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    // looks up and stores positions in `table`, which can be shared with other searches of the same game
+    pub fn with_table(mut self, table: Arc<Table>) -> Self {
+        self.table = Some(table);
+        self
+    }
+
+    // uses the exact results in `tablebase` instead of searching positions it has
+    pub fn with_tablebase(mut self, tablebase: Arc<Tablebase>) -> Self {
+        self.tablebase = Some(tablebase);
+        self
+    }
+
+    // values positions that aren't over when the depth runs out with `evaluator`, which can be
+    // `eval::Weights` or an `Arc<network::Network>`
+    pub fn with_evaluation(mut self, evaluator: impl Into<Evaluator>) -> Self {
+        self.evaluator = Some(evaluator.into());
+        self
+    }
+
+    // gives up the search as soon as `stop` is set
+    pub(crate) fn with_stop(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    // starts iterative deepening at `depth` instead of 1
+    pub(crate) fn starting_at_depth(mut self, depth: u32) -> Self {
+        self.first_depth = depth.max(1);
+        self
+    }
+
+    // the value of `b` and the best move for whoever is to move, searching `depth` moves ahead
+    // the move is `None` if the game is over or the cycle is about to be measured, since nobody picks then
+    // `b` is played on with `make_move` and left as it was given
+    pub fn search<const N: usize>(
+        &mut self,
+        b: &mut BoardState<N>,
+        depth: u32,
+    ) -> (f64, Option<Move>) {
+        debug_assert_eq!(b.rules().players, 2);
+        self.reset_stats();
+        self.node_limit = None;
+        self.deadline = None;
+        self.aborted = false;
+        self.value(b, depth, 0, -1.0, 1.0)
+    }
+
+    // searches one move deeper at a time until a limit is hit, the game is solved or a win is found
+    // a depth that's cut short is thrown away, so the best move is always from a search that finished
+    pub fn search_with_limits<const N: usize>(
+        &mut self,
+        b: &mut BoardState<N>,
+        limits: Limits,
+    ) -> SearchResult {
+        debug_assert_eq!(b.rules().players, 2);
+        let start = Instant::now();
+        self.node_limit = limits.nodes;
+        self.deadline = limits.time.map(|time| start + time);
+        self.aborted = false;
+        self.reset_stats();
+
+        let mut result = SearchResult {
+            value: 0.0,
+            best: None,
+            depth: 0,
+            nodes: 0,
+            solved: false,
+            book: false,
+        };
+        // something to play if not even the first depth finishes
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        result.best = moves
+            .first()
+            .cloned()
+            .filter(|_| b.collapse_chances().is_none());
+
+        for depth in self.first_depth..=limits.depth.unwrap_or(u32::MAX) {
+            self.cut_off = false;
+            let (value, best) = self.value(b, depth, 0, -1.0, 1.0);
+            if self.aborted {
+                break;
+            }
+            result.value = value;
+            result.best = best;
+            result.depth = depth;
+            if !self.cut_off {
+                result.solved = true;
+                break;
+            }
+            // nothing deeper can do better than a forced win or worse than a forced loss
+            if value.abs() >= 1.0 {
+                break;
+            }
+            // the next depth takes longer than all of the ones before it put together, so it's
+            // unlikely to finish once half the time is gone
+            if let Some(time) = limits.time {
+                if start.elapsed() > time / 2 {
+                    break;
+                }
+            }
+        }
+        result.nodes = self.nodes;
+        result
+    }
+
+    fn reset_stats(&mut self) {
+        self.nodes = 0;
+        self.cutoffs = 0;
+        self.first_move_cutoffs = 0;
+    }
+
+    // whether the search should give up, checked at every node
+    fn out_of_budget(&mut self) -> bool {
+        if self.node_limit.is_some_and(|limit| self.nodes > limit) {
+            self.aborted = true;
+        }
+        if self.nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.aborted = true;
+        }
+        if self
+            .stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+        {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    // `alpha` and `beta` are the values X and O can already get elsewhere, so once a position is
+    // known to be outside them it doesn't matter by how much, and the value returned is only a bound
+    fn value<const N: usize>(
+        &mut self,
+        b: &mut BoardState<N>,
+        depth: u32,
+        ply: usize,
+        mut alpha: f64,
+        mut beta: f64,
+    ) -> (f64, Option<Move>) {
+        self.nodes += 1;
+        if self.out_of_budget() {
+            return (0.0, None);
+        }
+        if let Some(score) = result(b) {
+            return (score, None);
+        }
+        // a chance node: the average of both outcomes, weighted by how likely they are
+        if let Some(chances) = b.collapse_chances() {
+            let mut expected = 0.0;
+            for &(m, p) in chances.iter() {
+                let undo = b.make_move(m);
+                expected += p * self.value(b, depth, ply, -1.0, 1.0).0;
+                b.unmake_move(undo);
+            }
+            return (expected, None);
+        }
+        // the first position is searched anyway, to have a move to play
+        if let Some(solution) = self.tablebase.as_ref().and_then(|tb| tb.probe(b)) {
+            if ply > 0 {
+                return (f64::from(solution.value), None);
+            }
+        }
+        if depth == 0 {
+            self.cut_off = true;
+            let value = self
+                .evaluator
+                .as_ref()
+                .map_or(0.0, |evaluator| evaluator.value(b));
+            return (value, None);
+        }
+        let key = b.key();
+        if let Some(entry) = self.table.as_ref().and_then(|table| table.probe(key)) {
+            if let (Some(m), true) = (entry.best, self.ordering) {
+                self.order.remember(key, m);
+            }
+            let deep_enough = entry.solved || u32::from(entry.depth) >= depth;
+            // a bound only helps if it's outside the window anyway
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.value >= beta,
+                Bound::Upper => entry.value <= alpha,
+            };
+            if ply > 0 && deep_enough && usable {
+                self.cut_off |= !entry.solved;
+                return (entry.value, entry.best);
+            }
+        }
+        let window = (alpha, beta);
+        // whether anything under this position gets cut off, to know if it's solved
+        let cut_off_before = std::mem::replace(&mut self.cut_off, false);
+
+        if self.moves.len() <= ply {
+            self.moves.push(Vec::new());
+        }
+        // taken out while the children use the buffers after it
+        let mut moves = std::mem::take(&mut self.moves[ply]);
+        b.valid_moves(&mut moves);
+        if self.ordering {
+            self.order.sort(b, &mut moves, ply);
+        }
+        // X is 0 and wants the highest value, O wants the lowest
+        let sign = if b.to_move() == 0 { 1.0 } else { -1.0 };
+        let mut best = (f64::NEG_INFINITY, None);
+        for (i, &m) in moves.iter().enumerate() {
+            let value = self.child_value(b, m, depth - 1, ply + 1, alpha, beta);
+            if self.aborted {
+                break;
+            }
+            if sign * value > best.0 {
+                best = (sign * value, Some(m));
+            }
+            if sign > 0.0 {
+                alpha = alpha.max(value);
+            } else {
+                beta = beta.min(value);
+            }
+            // the other player already has something better than this position, so they'll avoid it
+            if alpha >= beta {
+                self.cutoffs += 1;
+                if i == 0 {
+                    self.first_move_cutoffs += 1;
+                }
+                if self.ordering {
+                    self.order.cutoff(b, m, depth, ply);
+                }
+                break;
+            }
+        }
+        if let (Some(m), true, false) = (best.1, self.ordering, self.aborted) {
+            self.order.remember(key, m);
+        }
+        self.moves[ply] = moves;
+        let value = sign * best.0;
+        if let (Some(table), false) = (self.table.as_ref(), self.aborted) {
+            let bound = if value <= window.0 {
+                Bound::Upper
+            } else if value >= window.1 {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            let entry = Entry {
+                value,
+                bound,
+                best: best.1,
+                depth: depth.min(u32::from(u8::MAX)) as u8,
+                solved: !self.cut_off,
+            };
+            table.store(key, entry);
+        }
+        self.cut_off |= cut_off_before;
+        (value, best.1)
+    }
+
+    // the value after playing `m`, averaged over what it finds if it's a measurement
+    fn child_value<const N: usize>(
+        &mut self,
+        b: &mut BoardState<N>,
+        m: Move,
+        depth: u32,
+        ply: usize,
+        alpha: f64,
+        beta: f64,
+    ) -> f64 {
+        if let Move::Measure(sq) = m {
+            let mut expected = 0.0;
+            for (mov, p) in b.measure_chances(sq) {
+                // the chances only hold marks that are there, so this can't fail
+                if let Ok(undo) = b.make_measure(sq, mov) {
+                    expected += p * self.value(b, depth, ply, -1.0, 1.0).0;
+                    b.unmake_move(undo);
+                }
+            }
+            return expected;
+        }
+        let undo = b.make_move(m);
+        let value = self.value(b, depth, ply, alpha, beta).0;
+        b.unmake_move(undo);
+        value
+    }
+}
+
+impl Default for Expectimax {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Weights;
+    use crate::{CollapseChooser, Measuring, RuleSet};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // X in 0 and 4, O in 1 and 3, and X5 and O6 sharing squares 2 and 8 in a cycle that gets measured
+    // O6 landing in square 2 puts X5 in 8, completing 0-4-8 for X
+    // O6 landing in 8 puts X5 in 2, and nobody has a row yet
+    fn measured(weight: u8) -> BoardState {
+        let mut b = BoardState::new();
+        for &m in [
+            Move::Quantum(0, 1),
+            Move::Quantum(0, 1),
+            Move::Collapse { sq: 0, mov: 0 },
+            Move::Quantum(4, 3),
+            Move::Quantum(4, 3),
+            Move::Collapse { sq: 4, mov: 2 },
+            Move::Quantum(8, 2),
+            Move::Quantum(8, 2),
+        ]
+        .iter()
+        {
+            b.do_move(m);
+        }
+        b.with_rules(RuleSet {
+            collapse_chooser: CollapseChooser::Measurement { weight },
+            ..RuleSet::default()
+        })
+    }
+
+    #[test]
+    fn chances() {
+        let b = measured(30);
+        assert!(b.has_cycle());
+        assert_eq!(b.collapse_chooser(), None);
+        let [(low, p), (high, q)] = b.collapse_chances().unwrap();
+        assert_eq!(low, Move::Collapse { sq: 2, mov: 5 });
+        assert_eq!(high, Move::Collapse { sq: 8, mov: 5 });
+        assert!((p - 0.3).abs() < 1e-9 && (q - 0.7).abs() < 1e-9);
+        // players choose under the other rules
+        assert_eq!(BoardState::new().collapse_chances(), None);
+    }
+
+    #[test]
+    fn seeded_measurements_repeat() {
+        let outcomes = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..20)
+                .map(|_| {
+                    let mut b = measured(50);
+                    b.measure_cycle(&mut rng).unwrap();
+                    b.classic().is_x(8)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(outcomes(7), outcomes(7));
+        // with a fair coin, 20 measurements shouldn't all go the same way
+        let xs = outcomes(7).iter().filter(|&&x| x).count();
+        assert!(xs > 0 && xs < 20);
+        // a weight of 100 always puts the newest mark in its lower square
+        let mut b = measured(100);
+        b.measure_cycle(&mut StdRng::seed_from_u64(1)).unwrap();
+        assert!(b.classic().is_o(2) && b.classic().is_x(8));
+        assert!(b.classic().x_wins());
+    }
+
+    #[test]
+    fn expectimax_averages_measurements() {
+        for &weight in [0, 30, 100].iter() {
+            let mut b = measured(weight);
+            let before = b.clone();
+            let (value, m) = Expectimax::new().search(&mut b, 0);
+            assert_eq!(m, None);
+            assert!((value - f64::from(weight) / 100.0).abs() < 1e-9);
+            assert_eq!(b, before);
+        }
+    }
+
+    #[test]
+    fn expectimax_finds_wins() {
+        let mut b = measured(0);
+        b.measure_cycle(&mut StdRng::seed_from_u64(1)).unwrap();
+        // X is in 0, 2 and 4 and O in 1, 3 and 8, so X to move is threatening 2-4-6
+        assert!(b.classic().is_x(2) && b.classic().is_o(8));
+        let mut search = Expectimax::new();
+        let (value, m) = search.search(&mut b, 3);
+        assert!(value > 0.0, "{} {:?}", value, m);
+        assert!(m.is_some());
+        assert!(search.nodes > 1);
+    }
+
+    #[test]
+    fn expectimax_measures() {
+        let mut b = measured(50).with_rules(RuleSet::default());
+        b.do_move(Move::Collapse { sq: 8, mov: 5 });
+        let mut b = b.with_rules(RuleSet {
+            measuring: Measuring::Random,
+            ..RuleSet::default()
+        });
+        // X is in 0, 2 and 4, so finding X7 in 6 wins
+        b.do_move(Move::Quantum(6, 5));
+        b.do_move(Move::Quantum(5, 7));
+        // measuring 5 only wins half the time, when it finds O8 there and pushes X7 into 6
+        assert_eq!(b.measure_chances(5).len(), 2);
+        let (value, m) = Expectimax::new().search(&mut b, 1);
+        assert_eq!(m, Some(Move::Measure(6)));
+        assert!((value - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn expectimax_misere() {
+        let misere = |b: BoardState| {
+            let rules = RuleSet {
+                misere: true,
+                ..b.rules()
+            };
+            b.with_rules(rules)
+        };
+        // the same measurement as before, but now the row it might give X loses
+        let mut b = misere(measured(30));
+        let (value, _) = Expectimax::new().search(&mut b, 0);
+        assert!((value + 0.3).abs() < 1e-9);
+        let mut b = misere(measured(100));
+        b.measure_cycle(&mut StdRng::seed_from_u64(1)).unwrap();
+        assert!(b.classic().x_wins());
+        assert_eq!(result(&b), Some(-1.0));
+    }
+
+    #[test]
+    fn iterative_deepening() {
+        let mut b = measured(0);
+        b.measure_cycle(&mut StdRng::seed_from_u64(1)).unwrap();
+        let before = b.clone();
+        let mut search = Expectimax::new();
+        let limits = Limits {
+            depth: Some(3),
+            ..Limits::default()
+        };
+        let found = search.search_with_limits(&mut b, limits);
+        assert_eq!(b, before);
+        assert_eq!(found.depth, 3);
+        // the last depth is the same search as going straight there
+        let (value, m) = Expectimax::new().search(&mut b, 3);
+        assert_eq!((found.value, found.best), (value, m));
+
+        // the whole game from here fits in a few depths, so without limits it gets solved
+        let solved = search.search_with_limits(&mut b, Limits::default());
+        assert!(solved.solved || solved.value.abs() >= 1.0);
+        assert!(b.is_valid(solved.best.unwrap()));
+    }
+
+    #[test]
+    fn aborted_searches_keep_a_move() {
+        let mut b = BoardState::new();
+        let before = b.clone();
+        let mut search = Expectimax::new();
+        // not even depth 1 fits in 3 nodes, but there's still something to play
+        let limits = Limits {
+            nodes: Some(3),
+            ..Limits::default()
+        };
+        let found = search.search_with_limits(&mut b, limits);
+        assert_eq!(found.depth, 0);
+        assert!(b.is_valid(found.best.unwrap()));
+        assert_eq!(b, before);
+
+        // depth 1 is 37 nodes and depth 2 many more, so the second one is given up on
+        let limits = Limits {
+            nodes: Some(100),
+            ..Limits::default()
+        };
+        let found = search.search_with_limits(&mut b, limits);
+        assert_eq!(found.depth, 1);
+        assert_eq!(found.best, Expectimax::new().search(&mut b, 1).1);
+        assert_eq!(b, before);
+
+        // a time budget stops it too, and one that's run out before it starts doesn't depend on how
+        // fast the machine is: the time is only looked at every so many nodes, so depth 1 might
+        // still finish, but then the time is gone and it stops there
+        let limits = Limits {
+            time: Some(Duration::ZERO),
+            ..Limits::default()
+        };
+        let found = search.search_with_limits(&mut b, limits);
+        assert!(found.depth <= 1 && !found.solved);
+        assert!(b.is_valid(found.best.unwrap()));
+        assert_eq!(b, before);
+    }
+
+    #[test]
+    fn ordering_keeps_values() {
+        let (mut plain, mut ordered) = (0, 0);
+        for mut b in crate::ordering::sample_positions() {
+            let before = b.clone();
+            let mut search = Expectimax::new().with_ordering(false);
+            let (value, _) = search.search(&mut b, 4);
+            plain += search.nodes;
+            let mut search = Expectimax::new();
+            let (ordered_value, m) = search.search(&mut b, 4);
+            ordered += search.nodes;
+            assert!((value - ordered_value).abs() < 1e-9);
+            assert!(m.is_none_or(|m| b.is_valid(m)));
+            assert_eq!(b, before);
+        }
+        assert!(ordered < plain, "{} {}", ordered, plain);
+    }
+
+    #[test]
+    fn evaluation_at_the_horizon() {
+        let mut b = BoardState::new();
+        let weights = Weights::default();
+        let (value, m) = Expectimax::new().with_evaluation(weights).search(&mut b, 1);
+        // one move ahead, it's the best evaluation of any move
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let best = moves
+            .iter()
+            .map(|&m| {
+                let mut after = b.clone();
+                after.do_move(m);
+                weights.evaluate(&after).value
+            })
+            .fold(f64::NEG_INFINITY, f64::max);
+        assert_eq!(value, best);
+        assert!(value != 0.0 && value.abs() < 1.0);
+        assert!(b.is_valid(m.unwrap()));
+        // and alpha-beta gets the same value deeper down as searching everything
+        let (plain, _) = Expectimax::new()
+            .with_evaluation(weights)
+            .with_ordering(false)
+            .search(&mut b, 3);
+        let (ordered, _) = Expectimax::new().with_evaluation(weights).search(&mut b, 3);
+        assert!((plain - ordered).abs() < 1e-9);
+    }
+
+    #[test]
+    fn clock_allocation() {
+        let clock = Clock {
+            remaining: Duration::from_secs(60),
+            increment: Duration::from_secs(2),
+        };
+        // nine empty squares is about four more moves each
+        let b = BoardState::new();
+        assert_eq!(clock.allocate(&b), Duration::from_millis(16_500));
+        // with one square left, it never spends more than half the clock
+        let mut b = measured(0);
+        b.measure_cycle(&mut StdRng::seed_from_u64(1)).unwrap();
+        let short = Clock {
+            remaining: Duration::from_secs(1),
+            ..clock
+        };
+        assert_eq!(short.allocate(&b), Duration::from_millis(500));
+    }
+}
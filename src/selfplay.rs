@@ -0,0 +1,159 @@
+use crate::parallel::ParallelSearch;
+use crate::search::{result, Limits};
+use crate::{BoardState, Move, RuleSet};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// A game the computer played against itself
+#[derive(Clone, Debug)]
+pub struct Game {
+    // every position the game went through, from the one it started at to the finished board
+    pub positions: Vec<BoardState>,
+    // how it ended, from X's point of view like search values
+    pub result: f64,
+}
+
+/// How a match between two searches went, counted for the first one
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MatchResult {
+    pub wins: u32,
+    pub losses: u32,
+    pub ties: u32,
+}
+
+impl MatchResult {
+    // the share of the points the first search got, counting a tie as half a win
+    pub fn score(&self) -> f64 {
+        let games = self.wins + self.losses + self.ties;
+        (f64::from(self.wins) + f64::from(self.ties) / 2.0) / f64::from(games.max(1))
+    }
+}
+
+// a game `moves` moves in, with every move picked at random, so games from it don't all repeat
+// measurements and random measured squares use `rng` too
+pub fn random_opening<R: Rng>(rules: RuleSet, moves: u32, rng: &mut R) -> BoardState {
+    let mut b = BoardState::new().with_rules(rules);
+    let mut valid = Vec::new();
+    let mut played = 0;
+    while played < moves && !b.classic().game_is_over() {
+        if b.collapse_chances().is_some() {
+            b.measure_cycle(rng).expect("there's a cycle to measure");
+            continue;
+        }
+        b.valid_moves(&mut valid);
+        let &m = valid
+            .choose(rng)
+            .expect("a game that isn't over has a move");
+        play(&mut b, m, rng);
+        played += 1;
+    }
+    b
+}
+
+// plays `start` out with `players[0]` moving for X and `players[1]` for O, each searching with `limits`
+// their tables are cleared first, so nothing carries over from earlier games
+pub fn play_game<R: Rng>(
+    start: BoardState,
+    players: [&ParallelSearch; 2],
+    limits: Limits,
+    rng: &mut R,
+) -> Game {
+    for player in players.iter() {
+        player.table().clear();
+    }
+    let mut b = start;
+    let mut positions = vec![b.clone()];
+    while !b.classic().game_is_over() {
+        if b.collapse_chances().is_some() {
+            b.measure_cycle(rng).expect("there's a cycle to measure");
+        } else {
+            match players[b.to_move() as usize].search(&b, limits).best {
+                Some(m) => play(&mut b, m, rng),
+                None => break,
+            }
+        }
+        positions.push(b.clone());
+    }
+    Game {
+        result: result(&b).unwrap_or(0.0),
+        positions,
+    }
+}
+
+// plays `pairs` random openings of `opening` moves twice each, with `a` as X and then as O, so
+// neither search gets the better side of an opening more than the other
+pub fn play_match<R: Rng>(
+    a: &ParallelSearch,
+    b: &ParallelSearch,
+    rules: RuleSet,
+    pairs: u32,
+    opening: u32,
+    limits: Limits,
+    rng: &mut R,
+) -> MatchResult {
+    let mut tally = MatchResult::default();
+    for _ in 0..pairs {
+        let start = random_opening(rules, opening, rng);
+        for &(players, sign) in [([a, b], 1.0), ([b, a], -1.0)].iter() {
+            let game = play_game(start.clone(), players, limits, rng);
+            match sign * game.result {
+                r if r > 0.0 => tally.wins += 1,
+                r if r < 0.0 => tally.losses += 1,
+                _ => tally.ties += 1,
+            }
+        }
+    }
+    tally
+}
+
+// measured squares find a random mark, like they would in a real game
+pub(crate) fn play<R: Rng>(b: &mut BoardState, m: Move, rng: &mut R) {
+    match m {
+        Move::Measure(sq) => {
+            b.measure_randomly(sq, rng)
+                .expect("valid moves only measure squares with marks");
+        }
+        m => b.do_move(m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn games_play_out() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let start = random_opening(RuleSet::default(), 2, &mut rng);
+        assert_eq!(start.next_mov(), 2);
+        let search = ParallelSearch::new(1);
+        let limits = Limits {
+            depth: Some(1),
+            ..Limits::default()
+        };
+        let game = play_game(start.clone(), [&search, &search], limits, &mut rng);
+        assert_eq!(game.positions[0], start);
+        let last = game.positions.last().unwrap();
+        assert_eq!(result(last), Some(game.result));
+        assert!(game.positions[..game.positions.len() - 1]
+            .iter()
+            .all(|b| !b.classic().game_is_over()));
+    }
+
+    #[test]
+    fn matches_count_both_colors() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let search = ParallelSearch::new(1);
+        let limits = Limits {
+            depth: Some(1),
+            ..Limits::default()
+        };
+        let tally = play_match(&search, &search, RuleSet::default(), 3, 2, limits, &mut rng);
+        assert_eq!(tally.wins + tally.losses + tally.ties, 6);
+        // with the same search on both sides, whatever it wins as one color it loses as the other,
+        // unless a random measurement goes differently, which can't happen by these rules
+        assert_eq!(tally.wins, tally.losses);
+        assert_eq!(tally.score(), 0.5);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::Move;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What a stored value says about the real value of a position
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bound {
+    // the search finished inside its window, so this is the value
+    Exact,
+    // the search stopped early because the value was too good for X, so it's at least this much
+    Lower,
+    // or too good for O, so it's at most this much
+    Upper,
+}
+
+/// A position's search result, as stored in a `Table`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Entry {
+    // from X's point of view, like `Expectimax` values
+    pub value: f64,
+    pub bound: Bound,
+    pub best: Option<Move>,
+    // how many moves ahead the search looked
+    pub depth: u8,
+    // nothing below it was cut off by the depth, so it's as good as any deeper search
+    pub solved: bool,
+}
+
+/// Search results for positions by their `BoardState::key`, shared between threads without locks
+///
+/// Each slot is three `AtomicU64`s: the value, the rest of the entry packed into one word, and
+/// the key mixed with both of them. Two threads writing the same slot at once can leave it with
+/// halves of different entries, but then the key won't match and the slot just reads as empty.
+/// Keys don't include the rules, so a table should only be shared by searches of one game.
+pub struct Table {
+    slots: Vec<[AtomicU64; 3]>,
+}
+
+impl Table {
+    // a table with room for `slots` positions, rounded up to a power of two
+    pub fn new(slots: usize) -> Self {
+        let slots = slots.next_power_of_two();
+        Table {
+            slots: (0..slots)
+                .map(|_| [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)])
+                .collect(),
+        }
+    }
+
+    fn slot(&self, key: u64) -> &[AtomicU64; 3] {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let [check, value, data] = self.slot(key);
+        let (check, value, data) = (
+            check.load(Ordering::Relaxed),
+            value.load(Ordering::Relaxed),
+            data.load(Ordering::Relaxed),
+        );
+        // an empty slot has no data, since every stored entry has a bound
+        if data == 0 || check ^ value ^ data != key {
+            return None;
+        }
+        Some(Entry {
+            value: f64::from_bits(value),
+            bound: match data >> 24 & 0b11 {
+                1 => Bound::Exact,
+                2 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: decode(data as u16),
+            depth: (data >> 16) as u8,
+            solved: data >> 26 & 1 == 1,
+        })
+    }
+
+    // stores `entry` for `key`, unless its slot has a deeper search of the same position
+    pub fn store(&self, key: u64, entry: Entry) {
+        if let Some(old) = self.probe(key) {
+            if old.depth > entry.depth && !entry.solved {
+                return;
+            }
+        }
+        let bound = match entry.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let data = u64::from(encode(entry.best))
+            | u64::from(entry.depth) << 16
+            | bound << 24
+            | u64::from(entry.solved) << 26;
+        let value = entry.value.to_bits();
+        let [check_slot, value_slot, data_slot] = self.slot(key);
+        check_slot.store(key ^ value ^ data, Ordering::Relaxed);
+        value_slot.store(value, Ordering::Relaxed);
+        data_slot.store(data, Ordering::Relaxed);
+    }
+
+    // empties every slot, for starting a new game
+    pub fn clear(&self) {
+        for slot in self.slots.iter() {
+            for word in slot.iter() {
+                word.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// moves packed into 16 bits: a bit saying there is one, two bits for the kind of move, and five
+// bits for each of its numbers, since squares and move numbers are always below 32
+pub(crate) fn encode(m: Option<Move>) -> u16 {
+    let (kind, a, b) = match m {
+        None => return 0,
+        Some(Move::Quantum(sq1, sq2)) => (0, sq1, sq2),
+        Some(Move::Collapse { sq, mov }) => (1, sq, mov),
+        Some(Move::Classical(sq)) => (2, sq, 0),
+        Some(Move::Measure(sq)) => (3, sq, 0),
+    };
+    1 << 12 | kind << 10 | u16::from(a) << 5 | u16::from(b)
+}
+
+pub(crate) fn decode(bits: u16) -> Option<Move> {
+    if bits >> 12 == 0 {
+        return None;
+    }
+    let (a, b) = ((bits >> 5 & 0b11111) as u8, (bits & 0b11111) as u8);
+    Some(match bits >> 10 & 0b11 {
+        0 => Move::Quantum(a, b),
+        1 => Move::Collapse { sq: a, mov: b },
+        2 => Move::Classical(a),
+        _ => Move::Measure(a),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip() {
+        let table = Table::new(1000);
+        assert_eq!(table.slots.len(), 1024);
+        let moves = [
+            None,
+            Some(Move::Quantum(3, 31)),
+            Some(Move::Collapse { sq: 26, mov: 17 }),
+            Some(Move::Classical(8)),
+            Some(Move::Measure(0)),
+        ];
+        for (i, &best) in moves.iter().enumerate() {
+            let entry = Entry {
+                value: -0.3 * i as f64,
+                bound: [Bound::Exact, Bound::Lower, Bound::Upper][i % 3],
+                best,
+                depth: i as u8,
+                solved: i % 2 == 0,
+            };
+            let key = 0x1234_5678_9abc_def0 + i as u64;
+            table.store(key, entry);
+            assert_eq!(table.probe(key), Some(entry));
+        }
+        // a different key in the same slot doesn't match
+        assert_eq!(table.probe(0x1234_5678_9abc_def0 + 1024), None);
+        table.clear();
+        assert_eq!(table.probe(0x1234_5678_9abc_def0), None);
+    }
+
+    #[test]
+    fn deeper_entries_stay() {
+        let table = Table::new(16);
+        let entry = |depth, value| Entry {
+            value,
+            bound: Bound::Exact,
+            best: None,
+            depth,
+            solved: false,
+        };
+        table.store(5, entry(4, 0.5));
+        table.store(5, entry(2, 0.25));
+        assert_eq!(table.probe(5).unwrap().value, 0.5);
+        // but another position in the slot replaces it
+        table.store(5 + 16, entry(1, 0.75));
+        assert_eq!(table.probe(5), None);
+        assert_eq!(table.probe(5 + 16).unwrap().depth, 1);
+    }
+}
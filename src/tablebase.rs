@@ -0,0 +1,565 @@
+use crate::file::{FileError, Layout};
+use crate::search::result;
+use crate::{BoardState, ClassicalBoardState, CollapseChooser, Measuring, Move, RuleSet, Topology};
+use smallvec::SmallVec;
+use std::collections::HashMap;
+use std::{error, fmt, path::Path};
+
+/// What a tablebase knows about a position
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Solution {
+    // how the game ends with best play, from X's point of view: 1 for an X win, -1 for an O win, 0 for a tie
+    pub value: i8,
+    // how many more moves that takes, counting collapses, with the winner hurrying and the loser stalling
+    pub distance: u8,
+}
+
+/// Exact results for every position on the normal board with only a few squares left to fill
+///
+/// Built by `generate`, which solves every position with up to `max_empty` squares that aren't
+/// classical yet, and saved to a file to `load` later. Positions are stored by an index that's
+/// the same for boards that only differ by which moves have which numbers, or by being turned
+/// or flipped, so each one is only stored once. The results depend on the rules, so a tablebase
+/// only answers for boards played by the rules it was built for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tablebase {
+    rules: RuleSet,
+    max_empty: u8,
+    // sorted by index, for binary searching
+    entries: Vec<(u64, Solution)>,
+    symmetries: Vec<Vec<u8>>,
+}
+
+// marks are packed into 8 bits each after the 15 bits of the classical board, so only this many fit
+const MAX_EMPTY: u8 = 6;
+const LAYOUT: Layout = Layout {
+    kind: "tablebase",
+    magic: b"QTTB",
+    version: 2,
+    header: 14,
+    record: 10,
+};
+
+impl Tablebase {
+    // solves every position with up to `max_empty` squares left, by the given rules
+    // only rules without any chance in them can be solved exactly, and only for two players
+    pub fn generate(rules: RuleSet, max_empty: u8) -> Result<Self, TablebaseError> {
+        if !supported(rules) {
+            return Err(TablebaseError::Rules);
+        }
+        if max_empty > MAX_EMPTY {
+            return Err(TablebaseError::TooBig(max_empty));
+        }
+        let mut solver = Solver {
+            symmetries: Topology::classic().symmetries(),
+            solved: HashMap::new(),
+            moves: Vec::new(),
+        };
+        for mut b in positions(max_empty) {
+            b = b.with_rules(rules);
+            solver.solve(&mut b, 0);
+        }
+        let mut entries: Vec<_> = solver.solved.into_iter().collect();
+        entries.sort_unstable_by_key(|&(index, _)| index);
+        Ok(Tablebase {
+            rules,
+            max_empty,
+            entries,
+            symmetries: solver.symmetries,
+        })
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn max_empty(&self) -> u8 {
+        self.max_empty
+    }
+
+    // the number of positions stored
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // the result of `b` with best play, `None` if it's not in the tablebase
+    // finished games are always answered, with a distance of 0
+    pub fn probe<const N: usize>(&self, b: &BoardState<N>) -> Option<Solution> {
+        let empty = (0..b.cells())
+            .filter(|&sq| b.classic().is_empty(sq))
+            .count();
+        if empty > self.max_empty as usize
+            || b.rules() != self.rules
+            || !std::ptr::eq(b.topology(), Topology::classic())
+        {
+            return None;
+        }
+        if let Some(value) = result(b) {
+            return Some(Solution {
+                value: value as i8,
+                distance: 0,
+            });
+        }
+        let index = canonical_index(b, &self.symmetries);
+        self.entries
+            .binary_search_by_key(&index, |&(index, _)| index)
+            .ok()
+            .map(|found| self.entries[found].1)
+    }
+
+    // the best move in `b` and where it leads, `None` if `b` isn't in the tablebase or the game is over
+    pub fn best_move<const N: usize>(&self, b: &BoardState<N>) -> Option<(Move, Solution)> {
+        let mut b = b.clone();
+        let mover = b.to_move();
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let mut best: Option<(Move, Solution)> = None;
+        for m in moves {
+            let undo = b.make_move(m);
+            let solution = self.probe(&b);
+            b.unmake_move(undo);
+            let solution = solution?;
+            if best.is_none_or(|(_, best)| better(mover, solution, best)) {
+                best = Some((m, solution));
+            }
+        }
+        best.map(|(m, solution)| {
+            let distance = solution.distance + 1;
+            (
+                m,
+                Solution {
+                    distance,
+                    ..solution
+                },
+            )
+        })
+    }
+
+    // writes the tablebase in the format `from_bytes` reads:
+    // "QTTB", a version byte, the rules as four bytes like `RuleSet::to_bytes`, `max_empty`, the
+    // number of positions as 4 little-endian bytes, and then every position as its 8 byte
+    // little-endian index, value and distance
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = LAYOUT.start(self.entries.len());
+        bytes.extend_from_slice(&self.rules.to_bytes());
+        bytes.push(self.max_empty);
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for &(index, solution) in self.entries.iter() {
+            bytes.extend_from_slice(&index.to_le_bytes());
+            bytes.extend_from_slice(&[solution.value as u8, solution.distance]);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TablebaseError> {
+        LAYOUT.check(bytes)?;
+        let rules = LAYOUT.rules(bytes)?;
+        if !supported(rules) {
+            return Err(TablebaseError::Rules);
+        }
+        let max_empty = bytes[9];
+        if max_empty > MAX_EMPTY {
+            return Err(TablebaseError::TooBig(max_empty));
+        }
+        let entries: Vec<(u64, Solution)> = LAYOUT
+            .records(bytes, LAYOUT.count(bytes))?
+            .map(|entry| {
+                let mut index = [0; 8];
+                index.copy_from_slice(&entry[..8]);
+                let solution = Solution {
+                    value: entry[8] as i8,
+                    distance: entry[9],
+                };
+                (u64::from_le_bytes(index), solution)
+            })
+            .collect();
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(LAYOUT.format("the positions are out of order").into());
+        }
+        Ok(Tablebase {
+            rules,
+            max_empty,
+            entries,
+            symmetries: Topology::classic().symmetries(),
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TablebaseError> {
+        Ok(LAYOUT.save(path, self.to_bytes())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TablebaseError> {
+        Self::from_bytes(&LAYOUT.load(path)?)
+    }
+}
+
+/// Why a tablebase couldn't be made or loaded
+#[derive(Debug)]
+pub enum TablebaseError {
+    File(FileError),
+    // the rules have chance or more than two players in them, so there's no exact result
+    Rules,
+    TooBig(u8),
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TablebaseError::File(e) => e.fmt(f),
+            TablebaseError::Rules => write!(
+                f,
+                "tablebases can only be made for two players without measurements"
+            ),
+            TablebaseError::TooBig(max_empty) => write!(
+                f,
+                "tablebases can have up to {} empty squares, not {}",
+                MAX_EMPTY, max_empty
+            ),
+        }
+    }
+}
+
+impl error::Error for TablebaseError {}
+
+impl From<FileError> for TablebaseError {
+    fn from(e: FileError) -> Self {
+        TablebaseError::File(e)
+    }
+}
+
+fn supported(rules: RuleSet) -> bool {
+    let chance = matches!(rules.collapse_chooser, CollapseChooser::Measurement { .. });
+    rules.players == 2 && !chance && rules.measuring == Measuring::Off
+}
+
+// whether `a` is a better result than `b` for `mover`
+fn better(mover: u8, a: Solution, b: Solution) -> bool {
+    let sign = if mover == 0 { 1 } else { -1 };
+    let (a_value, b_value) = (sign * a.value, sign * b.value);
+    if a_value != b_value {
+        return a_value > b_value;
+    }
+    // a loss should take as long as possible, anything else as short as possible
+    if a_value < 0 {
+        a.distance > b.distance
+    } else {
+        a.distance < b.distance
+    }
+}
+
+// a quantum mark's owner and its two squares, without its move number
+type Mark = (u8, u8, u8);
+
+// the same number for positions that play out the same way: the classical marks, and the owner and
+// squares of every quantum mark, but not the mark numbers
+// each way of turning the board gives a number and the lowest one is used
+// the classical board takes the low 15 bits as a number in base 3, and each mark is 8 more bits above it
+fn canonical_index<const N: usize>(b: &BoardState<N>, symmetries: &[Vec<u8>]) -> u64 {
+    let cells = b.cells();
+    let marks: SmallVec<[Mark; 9]> = (0..b.next_mov())
+        .filter_map(|mov| {
+            let mut squares = (0..cells).filter(|&sq| b.quantum().is(mov, sq));
+            Some((b.player_of(mov), squares.next()?, squares.next()?))
+        })
+        .collect();
+    symmetries
+        .iter()
+        .map(|map| {
+            let classical = (0..cells).fold(0, |index, sq| {
+                let cell = b.classic().player_at(sq).map_or(0, |player| player + 1);
+                index + u64::from(cell) * 3u64.pow(u32::from(map[sq as usize]))
+            });
+            let mut codes: SmallVec<[u64; 9]> = marks
+                .iter()
+                .map(|&(owner, sq1, sq2)| {
+                    let (sq1, sq2) = (map[sq1 as usize], map[sq2 as usize]);
+                    let (low, high) = (sq1.min(sq2), sq1.max(sq2));
+                    1 + u64::from(owner) * 81 + u64::from(low) * 9 + u64::from(high)
+                })
+                .collect();
+            codes.sort_unstable();
+            codes
+                .iter()
+                .enumerate()
+                .fold(classical, |index, (i, &code)| index | code << (15 + 8 * i))
+        })
+        .min()
+        .unwrap()
+}
+
+// solves positions depth first, remembering every one it's seen
+struct Solver {
+    symmetries: Vec<Vec<u8>>,
+    solved: HashMap<u64, Solution>,
+    // one buffer of moves per ply, like the search has
+    moves: Vec<Vec<Move>>,
+}
+
+impl Solver {
+    fn solve(&mut self, b: &mut BoardState, ply: usize) -> Solution {
+        if let Some(value) = result(b) {
+            return Solution {
+                value: value as i8,
+                distance: 0,
+            };
+        }
+        let index = canonical_index(b, &self.symmetries);
+        if let Some(&solution) = self.solved.get(&index) {
+            return solution;
+        }
+        if self.moves.len() <= ply {
+            self.moves.push(Vec::new());
+        }
+        let mut moves = std::mem::take(&mut self.moves[ply]);
+        b.valid_moves(&mut moves);
+        let mover = b.to_move();
+        let mut best: Option<Solution> = None;
+        for &m in moves.iter() {
+            let undo = b.make_move(m);
+            let solution = self.solve(b, ply + 1);
+            b.unmake_move(undo);
+            if best.is_none_or(|best| better(mover, solution, best)) {
+                best = Some(solution);
+            }
+        }
+        self.moves[ply] = moves;
+        // a game that isn't over always has a move
+        let best = best.unwrap();
+        let solution = Solution {
+            distance: best.distance + 1,
+            ..best
+        };
+        self.solved.insert(index, solution);
+        solution
+    }
+}
+
+// every position on the normal board with between 1 and `max_empty` empty squares that could come up
+// in a game: no rows yet, X has made as many moves as O or one more, the quantum marks have at most
+// one cycle, and if they have one it was closed by the player who moved last
+// some of these can't actually be reached, but solving a few extra positions doesn't hurt
+fn positions(max_empty: u8) -> Vec<BoardState> {
+    let mut boards = Vec::new();
+    for code in 0..3u32.pow(9) {
+        let mut c = ClassicalBoardState::new();
+        let mut empty = SmallVec::<[u8; 9]>::new();
+        for sq in 0..9 {
+            match code / 3u32.pow(u32::from(sq)) % 3 {
+                0 => empty.push(sq),
+                cell => c.set(sq, cell as u8 - 1),
+            }
+        }
+        if empty.is_empty() || empty.len() > max_empty as usize || c.game_is_over() {
+            continue;
+        }
+        // each kind of mark is an owner and two empty squares
+        let mut kinds = Vec::new();
+        for (i, &sq1) in empty.iter().enumerate() {
+            for &sq2 in empty[i + 1..].iter() {
+                kinds.push((0, sq1, sq2));
+                kinds.push((1, sq1, sq2));
+            }
+        }
+        let classical = [c.squares_of(0).count_ones(), c.squares_of(1).count_ones()];
+        let mut chosen = Vec::new();
+        add_marks(&kinds, 0, &mut chosen, &mut |marks| {
+            if let Some(b) = build(c, classical, marks) {
+                boards.push(b);
+            }
+        });
+    }
+    boards
+}
+
+// calls `found` with every choice of marks from `kinds[start..]`, in order so each set only comes up once
+fn add_marks(kinds: &[Mark], start: usize, chosen: &mut Vec<Mark>, found: &mut dyn FnMut(&[Mark])) {
+    found(chosen);
+    for (i, &kind) in kinds.iter().enumerate().skip(start) {
+        chosen.push(kind);
+        // a second cycle would have been collapsed, and more marks can't get rid of it
+        if cycles(chosen) <= 1 {
+            add_marks(kinds, i, chosen, found);
+        }
+        chosen.pop();
+    }
+}
+
+// how many independent cycles the marks make, which is the marks minus the squares they
+// connect plus the number of separate groups of squares
+fn cycles(marks: &[Mark]) -> usize {
+    let mut group: [u8; 9] = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+    let mut cycles = 0;
+    for &(_, sq1, sq2) in marks.iter() {
+        let (g1, g2) = (group[sq1 as usize], group[sq2 as usize]);
+        if g1 == g2 {
+            cycles += 1;
+        } else {
+            for g in group.iter_mut().filter(|g| **g == g2) {
+                *g = g1;
+            }
+        }
+    }
+    cycles
+}
+
+// numbers the marks to build a board, `None` if they can't come from a real game
+// `classical` is how many classical marks X and O have
+fn build(c: ClassicalBoardState, classical: [u32; 2], marks: &[Mark]) -> Option<BoardState> {
+    let x_marks = marks.iter().filter(|&&(owner, _, _)| owner == 0).count() as u32;
+    let moves = [
+        classical[0] + x_marks,
+        classical[1] + marks.len() as u32 - x_marks,
+    ];
+    if moves[0] != moves[1] && moves[0] != moves[1] + 1 {
+        return None;
+    }
+    let next_mov = (moves[0] + moves[1]) as u8;
+    let last_owner = (next_mov + 1) % 2;
+    // give every mark a number of the right parity, with the `last` mark numbered as the newest move
+    let numbered = |last: Option<usize>| {
+        let mut free = [
+            (0..next_mov)
+                .filter(|mov| mov % 2 == 0)
+                .collect::<Vec<u8>>(),
+            (0..next_mov)
+                .filter(|mov| mov % 2 == 1)
+                .collect::<Vec<u8>>(),
+        ];
+        if last.is_some() {
+            free[last_owner as usize].retain(|&mov| mov != next_mov - 1);
+        }
+        let mut numbered: Vec<(u8, u8, u8)> = marks
+            .iter()
+            .enumerate()
+            .map(|(i, &(owner, sq1, sq2))| {
+                let mov = if Some(i) == last {
+                    next_mov - 1
+                } else {
+                    free[owner as usize].remove(0)
+                };
+                (mov, sq1, sq2)
+            })
+            .collect();
+        numbered.sort_unstable();
+        BoardState::from_marks(c, &numbered, next_mov)
+    };
+    if cycles(marks) == 0 {
+        return Some(numbered(None));
+    }
+    // the newest move has to be in the cycle, so try each of the last player's marks as that one
+    (0..marks.len())
+        .filter(|&i| marks[i].0 == last_owner)
+        .map(|i| (marks[i], numbered(Some(i))))
+        .find(|(mark, b)| {
+            let cycle: SmallVec<[u8; 9]> = b.cycle().cloned().collect();
+            cycle.contains(&mark.1) && cycle.contains(&mark.2)
+        })
+        .map(|(_, b)| b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Expectimax;
+    use std::sync::Arc;
+
+    #[test]
+    fn solves_endgames() {
+        let tablebase = Arc::new(Tablebase::generate(RuleSet::default(), 3).unwrap());
+        let mut rng = 0x2545_f491_4f6c_dd1d_u64;
+        let mut moves = Vec::new();
+        let mut checked = 0;
+        while checked < 20 {
+            // play randomly until there are only three squares left
+            let mut b = BoardState::new();
+            let empty = |b: &BoardState| (0..9).filter(|&sq| b.classic().is_empty(sq)).count();
+            while empty(&b) > 3 && !b.classic().game_is_over() {
+                b.valid_moves(&mut moves);
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                b.do_move(moves[rng as usize % moves.len()]);
+            }
+            if b.classic().game_is_over() {
+                continue;
+            }
+            checked += 1;
+            let solution = tablebase.probe(&b).unwrap();
+            // searching to the end of the game finds the same result
+            let (value, _) = Expectimax::new().search(&mut b, 20);
+            assert_eq!(value, f64::from(solution.value));
+            // which a search with the tablebase only has to look one move ahead for
+            let mut search = Expectimax::new().with_tablebase(tablebase.clone());
+            let (value, _) = search.search(&mut b, 1);
+            assert_eq!(value, f64::from(solution.value));
+            // and the best move gets a move closer to it
+            let (m, after) = tablebase.best_move(&b).unwrap();
+            assert_eq!(after, solution);
+            b.do_move(m);
+            let next = tablebase.probe(&b).unwrap();
+            assert_eq!(
+                (next.value, next.distance),
+                (solution.value, solution.distance - 1)
+            );
+        }
+        // with more squares left it doesn't know
+        assert_eq!(tablebase.probe(&BoardState::new()), None);
+    }
+
+    #[test]
+    fn symmetric_positions_share_entries() {
+        let tablebase = Tablebase::generate(RuleSet::default(), 2).unwrap();
+        // X in 0, 5, 7 and 6 and O in 1, 4 and 3, with 2 and 8 left for O's quantum move
+        let board = |squares: [u8; 7]| -> BoardState {
+            let mut c = ClassicalBoardState::new();
+            for (i, &sq) in squares.iter().enumerate() {
+                c.set(sq, (i % 2) as u8);
+            }
+            BoardState::from_marks(c, &[], 7)
+        };
+        let first = [0, 1, 5, 4, 7, 3, 6];
+        // and the same board turned or flipped
+        let map = &Topology::classic().symmetries()[2];
+        let turned = first.map(|sq| map[sq as usize]);
+        let (a, b) = (board(first), board(turned));
+        assert!(!a.classic().game_is_over() && !b.classic().game_is_over());
+        assert_ne!(a.classic(), b.classic());
+        assert!(tablebase.probe(&a).is_some());
+        assert_eq!(tablebase.probe(&a), tablebase.probe(&b));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let rules = RuleSet {
+            misere: true,
+            collapse_chooser: CollapseChooser::Creator,
+            ..RuleSet::default()
+        };
+        let tablebase = Tablebase::generate(rules, 2).unwrap();
+        let bytes = tablebase.to_bytes();
+        assert_eq!(bytes.len(), LAYOUT.header + LAYOUT.record * tablebase.len());
+        assert_eq!(Tablebase::from_bytes(&bytes).unwrap(), tablebase);
+        assert!(Tablebase::from_bytes(&bytes[..20]).is_err());
+        // the misère byte is either 0 or 1
+        let mut bad = bytes.clone();
+        bad[8] = 2;
+        assert!(Tablebase::from_bytes(&bad).is_err());
+        assert!(Tablebase::from_bytes(b"not a tablebase").is_err());
+
+        // boards played by other rules aren't answered
+        let b = BoardState::new();
+        assert_eq!(tablebase.probe(&b), None);
+        let measured = RuleSet {
+            measuring: Measuring::Oldest,
+            ..RuleSet::default()
+        };
+        assert!(matches!(
+            Tablebase::generate(measured, 2),
+            Err(TablebaseError::Rules)
+        ));
+    }
+}
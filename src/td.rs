@@ -0,0 +1,332 @@
+use crate::file::{FileError, Layout};
+use crate::search::result;
+use crate::selfplay::{play, MatchResult};
+use crate::{BoardState, Move, RuleSet, Topology};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::Path;
+
+const LAYOUT: Layout = Layout {
+    kind: "value table",
+    magic: b"QTTD",
+    version: 1,
+    header: 13,
+    record: 20,
+};
+
+/// A player that learns how good positions are by playing against itself (TD learning)
+///
+/// It keeps a value for every position it's seen, from X's point of view like search values, and
+/// plays the move leading to the best one. After each move of a training game the value of the
+/// position before it is pulled a little towards the value of the position after it, so the
+/// results at the ends of games spread back to the positions that lead to them. Positions that
+/// are turned or flipped versions of each other share a value, and positions it hasn't seen
+/// yet are worth 0. Only the normal 3x3 board is played, by the rules the table was made for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TdAgent {
+    rules: RuleSet,
+    // by `BoardState::canonical_key`, the value and how many times it's been updated
+    values: HashMap<u64, (f64, u32)>,
+    symmetries: Vec<Vec<u8>>,
+    // how far each update moves a value towards the one after it, between 0 and 1
+    // values updated fewer than 1 / rate times move further, to the average of their targets so far
+    rate: f64,
+    // how often a training game plays a random move instead of the best one, to try new things
+    exploration: f64,
+}
+
+impl TdAgent {
+    // an agent that hasn't learned anything yet
+    pub fn new(rules: RuleSet) -> Self {
+        TdAgent {
+            rules,
+            values: HashMap::new(),
+            symmetries: Topology::classic().symmetries(),
+            rate: 0.2,
+            exploration: 0.1,
+        }
+    }
+
+    // how far each update moves a value, 0.2 by default
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    // the chance of a random move in training games, 0.1 by default
+    pub fn with_exploration(mut self, exploration: f64) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    // the number of positions with a value
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // what the agent thinks `b` is worth, the result for finished games
+    // a cycle that collapses by measurement is worth the average of how it could collapse
+    pub fn value<const N: usize>(&self, b: &BoardState<N>) -> f64 {
+        if let Some(value) = result(b) {
+            return value;
+        }
+        if let Some(chances) = b.collapse_chances() {
+            return chances
+                .iter()
+                .map(|&(m, p)| {
+                    let mut after = b.clone();
+                    after.do_move(m);
+                    p * self.value(&after)
+                })
+                .sum();
+        }
+        let (key, _) = b.canonical_key(&self.symmetries);
+        self.values.get(&key).map_or(0.0, |&(value, _)| value)
+    }
+
+    // what the agent thinks playing `m` in `b` is worth, averaged over what it finds if it's a measurement
+    fn move_value<const N: usize>(&self, b: &BoardState<N>, m: Move) -> f64 {
+        match m {
+            Move::Measure(sq) => b
+                .measure_chances(sq)
+                .iter()
+                .map(|&(mov, p)| {
+                    let mut after = b.clone();
+                    // the chances only hold marks that are there, so this can't fail
+                    let _ = after.measure(sq, mov);
+                    p * self.value(&after)
+                })
+                .sum(),
+            m => {
+                let mut after = b.clone();
+                after.do_move(m);
+                self.value(&after)
+            }
+        }
+    }
+
+    // the move to the position the agent thinks is best for whoever's moving in `b`, `None` if
+    // the game is over or the cycle is about to be measured
+    pub fn best_move<const N: usize>(&self, b: &BoardState<N>) -> Option<Move> {
+        if b.collapse_chances().is_some() {
+            return None;
+        }
+        let mut moves = Vec::new();
+        b.valid_moves(&mut moves);
+        let sign = if b.to_move() == 0 { 1.0 } else { -1.0 };
+        moves
+            .into_iter()
+            .map(|m| (sign * self.move_value(b, m), m))
+            // the first of the best moves, so it always plays the same
+            .fold(None, |best: Option<(f64, Move)>, (value, m)| match best {
+                Some((best_value, _)) if best_value >= value => best,
+                _ => Some((value, m)),
+            })
+            .map(|(_, m)| m)
+    }
+
+    // plays one game against itself from the empty board and learns from it, returning how it ended
+    pub fn train<R: Rng>(&mut self, rng: &mut R) -> f64 {
+        let mut b = BoardState::new().with_rules(self.rules);
+        let mut moves = Vec::new();
+        while !b.classic().game_is_over() {
+            if b.collapse_chances().is_some() {
+                b.measure_cycle(rng).expect("there's a cycle to measure");
+                continue;
+            }
+            let before = b.clone();
+            let explore = rng.gen_bool(self.exploration);
+            let m = if explore {
+                b.valid_moves(&mut moves);
+                *moves
+                    .choose(rng)
+                    .expect("a game that isn't over has a move")
+            } else {
+                self.best_move(&b)
+                    .expect("a game that isn't over has a move")
+            };
+            play(&mut b, m, rng);
+            // a random move says nothing about how good `before` is with good play
+            if !explore {
+                let target = self.value(&b);
+                self.update(&before, target);
+            }
+        }
+        result(&b).unwrap_or(0.0)
+    }
+
+    // moves the value of `b` towards `target`
+    fn update<const N: usize>(&mut self, b: &BoardState<N>, target: f64) {
+        let (key, _) = b.canonical_key(&self.symmetries);
+        let (value, updates) = self.values.entry(key).or_insert((0.0, 0));
+        *updates += 1;
+        *value += self.rate.max(1.0 / f64::from(*updates)) * (target - *value);
+    }
+
+    // plays `games` games against a player that picks its moves at random, taking turns at going first
+    pub fn play_random<R: Rng>(&self, games: u32, rng: &mut R) -> MatchResult {
+        let mut moves = Vec::new();
+        self.play_against(games, rng, |b, rng| {
+            b.valid_moves(&mut moves);
+            moves.choose(rng).copied()
+        })
+    }
+
+    // plays `games` games against another agent, like `play_random`
+    pub fn play_agent<R: Rng>(&self, other: &TdAgent, games: u32, rng: &mut R) -> MatchResult {
+        self.play_against(games, rng, |b, _| other.best_move(b))
+    }
+
+    // plays `games` games with `opponent` picking the other side's moves, counted for this agent
+    fn play_against<R, F>(&self, games: u32, rng: &mut R, mut opponent: F) -> MatchResult
+    where
+        R: Rng,
+        F: FnMut(&BoardState, &mut R) -> Option<Move>,
+    {
+        let mut tally = MatchResult::default();
+        for game in 0..games {
+            // the agent is X in even games and O in odd ones
+            let agent = (game % 2) as u8;
+            let mut b = BoardState::new().with_rules(self.rules);
+            while !b.classic().game_is_over() {
+                if b.collapse_chances().is_some() {
+                    b.measure_cycle(rng).expect("there's a cycle to measure");
+                    continue;
+                }
+                let m = if b.to_move() == agent {
+                    self.best_move(&b)
+                } else {
+                    opponent(&b, rng)
+                };
+                play(&mut b, m.expect("a game that isn't over has a move"), rng);
+            }
+            let sign = if agent == 0 { 1.0 } else { -1.0 };
+            match sign * result(&b).unwrap_or(0.0) {
+                r if r > 0.0 => tally.wins += 1,
+                r if r < 0.0 => tally.losses += 1,
+                _ => tally.ties += 1,
+            }
+        }
+        tally
+    }
+
+    // writes the table in the format `from_bytes` reads:
+    // "QTTD", a version byte, the rules as four bytes like `RuleSet::to_bytes`, the number of
+    // positions as 4 little-endian bytes, and then every position as its 8 byte key, its value as 8
+    // bytes and how many times it's been updated as 4, all little-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<_> = self.values.iter().collect();
+        entries.sort_unstable_by_key(|&(&key, _)| key);
+        let mut bytes = LAYOUT.start(entries.len());
+        bytes.extend_from_slice(&self.rules.to_bytes());
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for (key, (value, updates)) in entries {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+            bytes.extend_from_slice(&updates.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        LAYOUT.check(bytes)?;
+        let mut agent = TdAgent::new(LAYOUT.rules(bytes)?);
+        for record in LAYOUT.records(bytes, LAYOUT.count(bytes))? {
+            let (mut key, mut value, mut updates) = ([0; 8], [0; 8], [0; 4]);
+            key.copy_from_slice(&record[..8]);
+            value.copy_from_slice(&record[8..16]);
+            updates.copy_from_slice(&record[16..]);
+            agent.values.insert(
+                u64::from_le_bytes(key),
+                (f64::from_le_bytes(value), u32::from_le_bytes(updates)),
+            );
+        }
+        Ok(agent)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FileError> {
+        LAYOUT.save(path, self.to_bytes())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, FileError> {
+        Self::from_bytes(&LAYOUT.load(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CollapseChooser, Measuring};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn symmetric_positions_share_values() {
+        let mut agent = TdAgent::new(RuleSet::default());
+        let mut b = BoardState::new();
+        b.do_move(Move::Quantum(0, 1));
+        // the first updates average what it's seen, so it isn't stuck near 0 for long
+        agent.update(&b, 1.0);
+        assert_eq!(agent.value(&b), 1.0);
+        // the same position flipped left to right
+        let mut flipped = BoardState::new();
+        flipped.do_move(Move::Quantum(1, 2));
+        assert_eq!(agent.value(&flipped), 1.0);
+        agent.update(&flipped, 0.0);
+        assert_eq!(agent.value(&b), 0.5);
+        assert_eq!(agent.len(), 1);
+        // positions it hasn't seen are even, so X goes for the one it thinks is good
+        let mut other = BoardState::new();
+        other.do_move(Move::Quantum(0, 4));
+        assert_eq!(agent.value(&other), 0.0);
+        let mut start = BoardState::new();
+        let m = agent.best_move(&start).unwrap();
+        start.do_move(m);
+        assert_eq!(agent.value(&start), agent.value(&b));
+    }
+
+    #[test]
+    fn learns_from_self_play() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let untrained = TdAgent::new(RuleSet::default());
+        let mut agent = untrained.clone();
+        for _ in 0..500 {
+            let result = agent.train(&mut rng);
+            assert!(result.abs() <= 1.0);
+        }
+        assert!(!agent.is_empty());
+        // random moves win too many games by luck to show it in a quick test, but an agent that
+        // only looks one move ahead loses to it
+        let tally = agent.play_agent(&untrained, 20, &mut rng);
+        assert!(tally.score() > 0.5, "{:?}", tally);
+        let random = agent.play_random(10, &mut rng);
+        assert_eq!(random.wins + random.losses + random.ties, 10);
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let rules = RuleSet {
+            collapse_chooser: CollapseChooser::Measurement { weight: 30 },
+            measuring: Measuring::Oldest,
+            ..RuleSet::default()
+        };
+        let mut agent = TdAgent::new(rules);
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..5 {
+            agent.train(&mut rng);
+        }
+        let bytes = agent.to_bytes();
+        assert_eq!(bytes.len(), LAYOUT.header + LAYOUT.record * agent.len());
+        assert_eq!(TdAgent::from_bytes(&bytes).unwrap(), agent);
+        assert!(TdAgent::from_bytes(&bytes[..LAYOUT.header + 3]).is_err());
+        assert!(TdAgent::from_bytes(b"not a value table").is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{Assignment, BoardState, WorldStats, MARKS};
+use std::fmt::Write;
+
+/// Plain text drawing of any grid or cube board, one line per row and a block per layer
+///
+/// Classical squares show their mark, quantum squares list the moves in them like `X1O4`.
+/// `collapsed` holds the steps of a collapse in progress to draw on top of the board,
+/// with the last one marked like `*X*`, and `heat` adds how often each quantum square is each player's.
+pub fn render<const N: usize>(
+    b: &BoardState<N>,
+    collapsed: &[Assignment],
+    heat: Option<&WorldStats>,
+) -> String {
+    let topo = b.topology();
+    // moves already placed by the collapse have their other halves hidden
+    let placed = collapsed.iter().fold(0u32, |mask, a| mask | 1 << a.mov);
+    let contents: Vec<String> = (0..topo.cells())
+        .map(|sq| {
+            let mut content = format!("{}:", sq + 1);
+            if let Some(pos) = collapsed.iter().position(|a| a.sq == sq) {
+                let mark = MARKS[b.player_of(collapsed[pos].mov) as usize];
+                if pos == collapsed.len() - 1 {
+                    write!(content, "*{}*", mark).unwrap();
+                } else {
+                    content.push(mark);
+                }
+            } else if let Some(player) = b.classic().player_at(sq) {
+                content.push(MARKS[player as usize]);
+            } else {
+                for mov in (0..b.next_mov()).filter(|&mov| b.quantum().is(mov, sq)) {
+                    if placed & (1 << mov) == 0 {
+                        let mark = MARKS[b.player_of(mov) as usize];
+                        write!(content, "{}{}", mark, mov + 1).unwrap();
+                    }
+                }
+                if let Some(stats) = heat.filter(|_| b.quantum().mask_in(sq) > 0) {
+                    for player in 0..b.rules().players {
+                        let percent = 100.0 * stats.fraction(sq, player);
+                        write!(content, " {}{:.0}", MARKS[player as usize], percent).unwrap();
+                    }
+                }
+            }
+            content
+        })
+        .collect();
+    let width = contents.iter().map(|c| c.len()).max().unwrap_or(0);
+
+    let mut buf = String::new();
+    let layer_size = topo.width() as usize * topo.height() as usize;
+    for layer in 0..topo.depth() as usize {
+        if topo.depth() > 1 {
+            writeln!(buf, "Layer {}", layer + 1).unwrap();
+        }
+        for row in 0..topo.height() as usize {
+            if row > 0 {
+                let divider = vec!["-".repeat(width + 2); topo.width() as usize];
+                writeln!(buf, "{}", divider.join("+")).unwrap();
+            }
+            let cells: Vec<String> = (0..topo.width() as usize)
+                .map(|col| {
+                    let content = &contents[layer * layer_size + row * topo.width() as usize + col];
+                    format!(" {:width$} ", content, width = width)
+                })
+                .collect();
+            writeln!(buf, "{}", cells.join("|")).unwrap();
+        }
+        writeln!(buf).unwrap();
+    }
+    // write the cycle if the board has it
+    if b.has_cycle() {
+        write!(buf, "Cycle:").unwrap();
+        for sq in b.cycle() {
+            write!(buf, " {}", sq + 1).unwrap();
+        }
+        writeln!(buf).unwrap();
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Move, Topology};
+
+    #[test]
+    fn cube_layers() {
+        let mut b = BoardState::<27>::with_topology(Topology::cube());
+        b.do_move(Move::Quantum(0, 26));
+        b.do_move(Move::Quantum(13, 26));
+        let text = render(&b, &[], None);
+        assert_eq!(text.matches("Layer").count(), 3);
+        assert!(text.contains("1:X1 "));
+        assert!(text.contains("14:O2 "));
+        assert!(text.contains("27:X1O2"));
+
+        b.do_move(Move::Quantum(0, 13));
+        let before = b.clone();
+        let trace = b.collapse(0, 0).unwrap();
+        let steps: Vec<_> = trace.iter().cloned().collect();
+        let text = render(&before, &steps[..2], None);
+        assert!(text.contains("1:X "));
+        assert!(text.contains("14:*X*"));
+        // X1 has been placed, so only O2 is left in the last square
+        assert!(text.contains("27:O2 "));
+        let text = render(&b, &[], None);
+        assert!(text.contains("27:O "));
+        assert!(!text.contains("Cycle"));
+    }
+}
@@ -0,0 +1,409 @@
+use lazy_static::lazy_static;
+use std::{
+    error, fmt, fs, io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+/// The shape of a board: how many cells it has and which sets of cells win
+///
+/// Boards keep a `&'static Topology`, so the same one is shared by every board of a game
+/// and they stay cheap to copy. The built-in 3x3 board is `Topology::classic()`, others can be
+/// built and then `leak`ed, which keeps one copy of each different topology for the rest of the
+/// program. So boards can tell if they have the same shape by comparing the addresses with
+/// `std::ptr::eq`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topology {
+    cells: u8,
+    // each winning line as a mask with a bit for each of its cells
+    lines: Vec<u32>,
+    // the same lines, spread out to line up with the X bits of a `ClassicalBoardState`
+    x_lines: Vec<u64>,
+    // grid dimensions, used for printing
+    // cubes are printed as `depth` layers, flat boards have a depth of 1
+    width: u8,
+    height: u8,
+    depth: u8,
+}
+
+lazy_static! {
+    static ref CLASSIC: Topology = Topology::grid(3, 3);
+    static ref CUBE: Topology = Topology::new(27, straight_lines(3, 3, 3), 3, 3, 3);
+    // every topology that's been leaked, so the same one is only ever leaked once
+    static ref LEAKED: Mutex<Vec<&'static Topology>> = Mutex::new(Vec::new());
+}
+
+impl Topology {
+    // boards store two bits per cell in a u64 and a bit per move in a u32
+    pub const MAX_CELLS: usize = 32;
+
+    // the normal 3x3 tic-tac-toe board
+    pub fn classic() -> &'static Topology {
+        &CLASSIC
+    }
+
+    // a 3x3x3 cube where any of the 49 straight lines of 3 wins, including the ones between layers
+    // cell `sq` is in layer `sq / 9`, and each layer is numbered like the normal board
+    pub fn cube() -> &'static Topology {
+        &CUBE
+    }
+
+    // a `size` by `size` grid where `win_len` in a row, column or diagonal wins
+    pub fn grid(size: u8, win_len: u8) -> Self {
+        assert!(size as usize * size as usize <= Self::MAX_CELLS);
+        assert!(win_len >= 1 && win_len <= size);
+        Self::new(size * size, straight_lines(2, size, win_len), size, size, 1)
+    }
+
+    fn new(cells: u8, lines: Vec<u32>, width: u8, height: u8, depth: u8) -> Self {
+        let x_lines = lines
+            .iter()
+            .map(|&line| {
+                (0..cells)
+                    .filter(|&cell| line & (1 << cell) > 0)
+                    .fold(0, |x_line, cell| x_line | 1 << (2 * cell))
+            })
+            .collect();
+        Topology {
+            cells,
+            lines,
+            x_lines,
+            width,
+            height,
+            depth,
+        }
+    }
+
+    // reads a board definition from a file, see `parse` for the format
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TopologyError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // builds a board from a definition like this one, for a 3x3 board where the lines wrap around:
+    //
+    //     # anything after a # is ignored
+    //     cells 9
+    //     grid 3 3       # width, height and optionally layers, for printing the board
+    //     line 1 2 3     # cells are numbered from 1
+    //     line 1 5 9
+    //     line 3 4 8     # a diagonal that wraps around the edge
+    //     ...
+    //
+    // `grid` can be left out, then the cells are printed in a single row
+    pub fn parse(definition: &str) -> Result<Self, TopologyError> {
+        let mut cells = None;
+        let mut grid = None;
+        let mut lines = Vec::new();
+        for (idx, text) in definition.lines().enumerate() {
+            let err = |message: &str| TopologyError::Parse {
+                line: idx + 1,
+                message: message.to_string(),
+            };
+            let mut words = text.split('#').next().unwrap().split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let nums = words
+                .map(|word| word.parse::<u8>())
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| err("expected numbers"))?;
+            match keyword {
+                "cells" => {
+                    if cells.is_some() {
+                        return Err(err("cells is given twice"));
+                    }
+                    match nums[..] {
+                        [n] if n >= 2 && n as usize <= Self::MAX_CELLS => cells = Some(n),
+                        [_] => return Err(err("there must be between 2 and 32 cells")),
+                        _ => return Err(err("cells takes one number")),
+                    }
+                }
+                "grid" => match nums[..] {
+                    [width, height] => grid = Some((width, height, 1)),
+                    [width, height, depth] => grid = Some((width, height, depth)),
+                    _ => return Err(err("grid takes a width, height and optionally layers")),
+                },
+                "line" => {
+                    let n = cells.ok_or_else(|| err("cells must be given before any lines"))?;
+                    if nums.is_empty() {
+                        return Err(err("a line needs at least one cell"));
+                    }
+                    let mut line = 0u32;
+                    for &cell in nums.iter() {
+                        if cell == 0 || cell > n {
+                            return Err(err("cell is not on the board"));
+                        }
+                        line |= 1 << (cell - 1);
+                    }
+                    lines.push(line);
+                }
+                _ => return Err(err("expected cells, grid or line")),
+            }
+        }
+        let cells = cells.ok_or(TopologyError::Parse {
+            line: 0,
+            message: "the number of cells is never given".to_string(),
+        })?;
+        let (width, height, depth) = grid.unwrap_or((cells, 1, 1));
+        if width as usize * height as usize * depth as usize != cells as usize {
+            return Err(TopologyError::Parse {
+                line: 0,
+                message: "the grid doesn't have the same number of cells as the board".to_string(),
+            });
+        }
+        Ok(Self::new(cells, lines, width, height, depth))
+    }
+
+    // gives the topology a 'static lifetime so boards can use it
+    // the memory is never freed, but a topology equal to one that's already been leaked, or to a
+    // built-in one, gives back that one instead, so building the same board over and over only
+    // leaks it the first time
+    pub fn leak(self) -> &'static Topology {
+        if self == *CLASSIC {
+            return &CLASSIC;
+        }
+        if self == *CUBE {
+            return &CUBE;
+        }
+        // nothing can be left half done while the lock is held, so a panic elsewhere doesn't matter
+        let mut leaked = LEAKED.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&topo) = leaked.iter().find(|&&topo| *topo == self) {
+            return topo;
+        }
+        let topo = Box::leak(Box::new(self));
+        leaked.push(topo);
+        topo
+    }
+
+    pub fn cells(&self) -> u8 {
+        self.cells
+    }
+
+    pub fn lines(&self) -> &[u32] {
+        &self.lines
+    }
+
+    pub(crate) fn x_lines(&self) -> &[u64] {
+        &self.x_lines
+    }
+
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    // the ways a flat square board can be turned and flipped without changing which sets of cells win
+    // each one says where every cell goes, and the first is always leaving them where they are
+    // boards that aren't square, or whose lines don't survive being turned, only have that one
+    pub fn symmetries(&self) -> Vec<Vec<u8>> {
+        let identity: Vec<u8> = (0..self.cells).collect();
+        let mut symmetries = vec![identity];
+        if self.depth != 1 || self.width != self.height {
+            return symmetries;
+        }
+        let size = self.width;
+        for turns in 0..4 {
+            for &flip in [false, true].iter() {
+                let map: Vec<u8> = (0..self.cells)
+                    .map(|cell| {
+                        let (mut row, mut col) = (cell / size, cell % size);
+                        if flip {
+                            col = size - 1 - col;
+                        }
+                        for _ in 0..turns {
+                            let turned = (col, size - 1 - row);
+                            row = turned.0;
+                            col = turned.1;
+                        }
+                        row * size + col
+                    })
+                    .collect();
+                let moved = |line: u32| {
+                    (0..self.cells)
+                        .filter(|&cell| line & 1 << cell > 0)
+                        .fold(0u32, |moved, cell| moved | 1 << map[cell as usize])
+                };
+                let keeps_lines = self
+                    .lines
+                    .iter()
+                    .all(|&line| self.lines.contains(&moved(line)));
+                if keeps_lines && !symmetries.contains(&map) {
+                    symmetries.push(map);
+                }
+            }
+        }
+        symmetries
+    }
+}
+
+/// Why a board definition couldn't be loaded
+#[derive(Debug)]
+pub enum TopologyError {
+    Io(io::Error),
+    // line 0 means the problem is with the definition as a whole
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TopologyError::Io(e) => write!(f, "couldn't read the board definition: {}", e),
+            TopologyError::Parse { line: 0, message } => write!(f, "{}", message),
+            TopologyError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for TopologyError {}
+
+impl From<io::Error> for TopologyError {
+    fn from(e: io::Error) -> Self {
+        TopologyError::Io(e)
+    }
+}
+
+// every straight run of `win_len` cells in a grid with `dims` dimensions of `size` cells each
+// cells are numbered with the first dimension changing fastest, so in 2d it's `row * size + col`
+fn straight_lines(dims: u32, size: u8, win_len: u8) -> Vec<u32> {
+    let size = size as i32;
+    let cells = size.pow(dims);
+    let coord = |cell: i32, d: u32| cell / size.pow(d) % size;
+    let mut lines = Vec::new();
+    // a direction has a step of -1, 0 or 1 along each dimension
+    for dir in 0..3i32.pow(dims) {
+        let step = |d: u32| dir / 3i32.pow(d) % 3 - 1;
+        // only one of each pair of opposite directions, the one whose last non-zero step is positive
+        match (0..dims).rev().map(step).find(|&s| s != 0) {
+            Some(1) => {}
+            _ => continue,
+        }
+        for start in 0..cells {
+            let end_in_bounds = (0..dims).all(|d| {
+                let end = coord(start, d) + step(d) * (win_len as i32 - 1);
+                end >= 0 && end < size
+            });
+            if !end_in_bounds {
+                continue;
+            }
+            let line = (0..win_len as i32).fold(0, |line, i| {
+                let cell: i32 = (0..dims)
+                    .map(|d| (coord(start, d) + step(d) * i) * size.pow(d))
+                    .sum();
+                line | 1 << cell
+            });
+            // a line of one cell comes out of every direction
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_counts() {
+        assert_eq!(Topology::classic().lines().len(), 8);
+        // 4 rows, 4 columns and 2 diagonals
+        assert_eq!(Topology::grid(4, 4).lines().len(), 10);
+        // 2 per row and column, and 8 diagonals
+        assert_eq!(Topology::grid(4, 3).lines().len(), 24);
+        assert_eq!(Topology::grid(5, 5).lines().len(), 12);
+        assert_eq!(Topology::grid(5, 4).lines().len(), 28);
+        assert_eq!(Topology::grid(3, 1).lines().len(), 9);
+    }
+
+    #[test]
+    fn symmetries() {
+        let symmetries = Topology::classic().symmetries();
+        assert_eq!(symmetries.len(), 8);
+        assert_eq!(symmetries[0], (0..9).collect::<Vec<u8>>());
+        // every one of them keeps the center where it is and moves corners to corners
+        for map in symmetries.iter() {
+            assert_eq!(map[4], 4);
+            assert!([0, 2, 6, 8].contains(&map[0]));
+        }
+        assert_eq!(Topology::grid(4, 3).symmetries().len(), 8);
+        assert_eq!(Topology::cube().symmetries().len(), 1);
+        // a row that isn't matched by a column can't be turned
+        let lopsided = Topology::parse("cells 4\ngrid 2 2\nline 1 2").unwrap();
+        assert_eq!(lopsided.symmetries().len(), 2);
+    }
+
+    #[test]
+    fn leaked_once() {
+        let grid = Topology::grid(4, 3).leak();
+        assert!(std::ptr::eq(grid, Topology::grid(4, 3).leak()));
+        assert!(!std::ptr::eq(grid, Topology::grid(4, 4).leak()));
+        assert!(std::ptr::eq(
+            Topology::grid(3, 3).leak(),
+            Topology::classic()
+        ));
+    }
+
+    #[test]
+    fn cube_lines() {
+        let cube = Topology::cube();
+        assert_eq!(cube.cells(), 27);
+        assert_eq!(cube.lines().len(), 49);
+        // through the middle of every layer
+        assert!(cube.lines().contains(&(1 << 4 | 1 << 13 | 1 << 22)));
+        // corner to corner
+        assert!(cube.lines().contains(&(1 << 0 | 1 << 13 | 1 << 26)));
+        assert!(cube.lines().contains(&(1 << 2 | 1 << 13 | 1 << 24)));
+        // a diagonal of the top layer
+        assert!(cube.lines().contains(&(1 << 2 | 1 << 4 | 1 << 6)));
+        assert!(cube.lines().iter().all(|line| line.count_ones() == 3));
+    }
+
+    #[test]
+    fn parse_definitions() {
+        let torus = Topology::parse(include_str!("../boards/torus.txt")).unwrap();
+        assert_eq!(torus.cells(), 9);
+        assert_eq!(torus.lines().len(), 12);
+        assert_eq!((torus.width(), torus.height(), torus.depth()), (3, 3, 1));
+        // the same lines as the normal board, plus the diagonals that wrap around
+        for line in Topology::classic().lines() {
+            assert!(torus.lines().contains(line));
+        }
+        assert!(torus.lines().contains(&0b010001100));
+
+        // the magic square board has the same lines as the normal board, just numbered differently
+        let magic = Topology::parse(include_str!("../boards/magic15.txt")).unwrap();
+        assert_eq!(magic.lines().len(), 8);
+        assert_eq!((magic.width(), magic.height()), (9, 1));
+
+        let line = |definition| match Topology::parse(definition) {
+            Err(TopologyError::Parse { line, .. }) => line,
+            _ => panic!("{} should fail to parse", definition),
+        };
+        assert_eq!(line("cells 4\nline 1 5"), 2);
+        assert_eq!(line("line 1 2\ncells 4"), 1);
+        assert_eq!(line("cells 40"), 1);
+        assert_eq!(line("cells 4\n\n# comment\nrow 1 2"), 4);
+        assert_eq!(line("cells 4\nline 1 two"), 2);
+        assert_eq!(line("cells 4\ngrid 3 2"), 0);
+        assert_eq!(line("# nothing"), 0);
+        assert!(Topology::load("boards/does-not-exist.txt").is_err());
+    }
+
+    #[test]
+    fn classic_lines() {
+        let lines = Topology::classic().lines();
+        for &line in [0b111, 0b111000, 0b100100100, 0b100010001, 0b001010100].iter() {
+            assert!(lines.contains(&line));
+        }
+        assert!(lines.iter().all(|line| line.count_ones() == 3));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{BoardState, ClassicalBoardState, Move, Outcome, MARKS};
+use std::fmt::Write;
+
+/// A move on an ultimate board: a normal move made in one of the nine sub-boards
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UltimateMove {
+    pub board: u8,
+    pub mov: Move,
+}
+
+/// Ultimate quantum tic-tac-toe: nine quantum boards in the squares of a classical meta-board
+///
+/// The square a move is made in sends the next player to the sub-board in the same position,
+/// and winning a sub-board claims its square of the meta-board. For a quantum move that's the
+/// first of its two squares. A sub-board that's already over sends them nowhere, so they can play
+/// in any sub-board that isn't over. A cycle has to be collapsed by the next player before they
+/// make their own move, and the collapse doesn't change where that move goes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UltimateBoard {
+    boards: [BoardState; 9],
+    meta: ClassicalBoardState,
+    // 0 for X and 1 for O
+    to_move: u8,
+    // the sub-board the next quantum move has to go in, `None` if it can go anywhere
+    target: Option<u8>,
+}
+
+impl UltimateBoard {
+    pub fn new() -> Self {
+        UltimateBoard {
+            boards: Default::default(),
+            meta: ClassicalBoardState::new(),
+            to_move: 0,
+            target: None,
+        }
+    }
+
+    pub fn board(&self, board: u8) -> &BoardState {
+        &self.boards[board as usize]
+    }
+
+    // which sub-boards have been won by who
+    pub fn meta(&self) -> &ClassicalBoardState {
+        &self.meta
+    }
+
+    pub fn to_move(&self) -> u8 {
+        self.to_move
+    }
+
+    // the sub-board with a cycle waiting to be collapsed, there can only be one at a time
+    pub fn pending_cycle(&self) -> Option<u8> {
+        (0..9).find(|&board| self.boards[board as usize].has_cycle())
+    }
+
+    // the sub-board the next quantum move has to go in, `None` if any that isn't over will do
+    pub fn target(&self) -> Option<u8> {
+        self.target.filter(|&board| self.is_open(board))
+    }
+
+    // whether a sub-board can still take new moves
+    fn is_open(&self, board: u8) -> bool {
+        !self.boards[board as usize].classic().game_is_over()
+    }
+
+    // who won, `None` if the game isn't over yet
+    // a sub-board that ends in a tie doesn't count for anyone
+    pub fn outcome(&self) -> Option<Outcome> {
+        let rows = (self.meta.x_wins(), self.meta.o_wins());
+        let playable = self.pending_cycle().is_some() || (0..9).any(|board| self.is_open(board));
+        if rows == (false, false) && playable {
+            return None;
+        }
+        Some(match rows {
+            (true, false) => Outcome::Win(0),
+            (false, true) => Outcome::Win(1),
+            _ => Outcome::Tie,
+        })
+    }
+
+    pub fn valid_moves(&self, store: &mut Vec<UltimateMove>) {
+        store.clear();
+        if self.outcome().is_some() {
+            return;
+        }
+        let mut moves = Vec::new();
+        let boards = match (self.pending_cycle(), self.target()) {
+            (Some(board), _) => board..board + 1,
+            (None, Some(board)) => board..board + 1,
+            (None, None) => 0..9,
+        };
+        for board in
+            boards.filter(|&board| self.is_open(board) || self.boards[board as usize].has_cycle())
+        {
+            self.boards[board as usize].valid_moves(&mut moves);
+            for &mov in moves.iter() {
+                store.push(UltimateMove { board, mov });
+                // the first square says where the next player goes, so both orders are different moves
+                if let Move::Quantum(sq1, sq2) = mov {
+                    store.push(UltimateMove {
+                        board,
+                        mov: Move::Quantum(sq2, sq1),
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn is_valid(&self, m: UltimateMove) -> bool {
+        if m.board >= 9 || self.outcome().is_some() {
+            return false;
+        }
+        let b = &self.boards[m.board as usize];
+        match (m.mov, self.pending_cycle()) {
+            // the cycle has to be collapsed first, and by the next player
+            (Move::Collapse { .. }, Some(board)) => {
+                board == m.board && b.is_valid_for(self.to_move, m.mov)
+            }
+            (Move::Collapse { .. }, None) | (_, Some(_)) => false,
+            // the sub-board might think it's the other player's turn, but `do_move` skips a mark number
+            // to fix that, so only the square rules matter
+            (_, None) => {
+                self.is_open(m.board)
+                    && self.target().is_none_or(|target| target == m.board)
+                    && b.is_valid(m.mov)
+            }
+        }
+    }
+
+    pub fn do_move(&mut self, m: UltimateMove) {
+        debug_assert!(self.is_valid(m));
+        let b = &mut self.boards[m.board as usize];
+        match m.mov {
+            Move::Collapse { .. } => b.do_move(m.mov),
+            Move::Quantum(sq, _) | Move::Classical(sq) | Move::Measure(sq) => {
+                // marks are numbered per sub-board and a number is skipped when the same player moves
+                // there twice in a row, but each of the 9 marks a sub-board can take skips at most one,
+                // so it uses at most 18 of the 32 numbers a `QuantumBoardState` holds
+                if b.to_move() != self.to_move {
+                    b.pass();
+                }
+                b.do_move(m.mov);
+                self.target = Some(sq);
+                self.to_move = 1 - self.to_move;
+            }
+        }
+        if let Some(Outcome::Win(player)) = b.outcome() {
+            self.meta.set(m.board, player);
+        }
+    }
+}
+
+impl Default for UltimateBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Plain text drawing of all nine sub-boards laid out like the meta-board
+///
+/// Each square shows its mark or the quantum moves in it like `X1O4`, with `||` and `==` between
+/// the sub-boards. Sub-boards that have been won are labelled underneath.
+pub fn render(u: &UltimateBoard) -> String {
+    let contents: Vec<Vec<String>> = u
+        .boards
+        .iter()
+        .map(|b| {
+            (0..9)
+                .map(|sq| match b.classic().player_at(sq) {
+                    Some(player) => MARKS[player as usize].to_string(),
+                    None => (0..b.next_mov())
+                        .filter(|&mov| b.quantum().is(mov, sq))
+                        .map(|mov| format!("{}{}", MARKS[b.player_of(mov) as usize], mov + 1))
+                        .collect(),
+                })
+                .collect()
+        })
+        .collect();
+    let width = contents
+        .iter()
+        .flatten()
+        .map(|c| c.len())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut buf = String::new();
+    for big_row in 0..3 {
+        if big_row > 0 {
+            // nine squares, two `|` in each sub-board and two ` || ` between them
+            writeln!(buf, "{}", "=".repeat(9 * width + 6 + 8)).unwrap();
+        }
+        for row in 0..3 {
+            let boards: Vec<String> = (0..3)
+                .map(|big_col| {
+                    let cells = &contents[3 * big_row + big_col][3 * row..3 * row + 3];
+                    let cells: Vec<String> = cells
+                        .iter()
+                        .map(|c| format!("{:width$}", c, width = width))
+                        .collect();
+                    cells.join("|")
+                })
+                .collect();
+            writeln!(buf, "{}", boards.join(" || ")).unwrap();
+        }
+    }
+    writeln!(buf).unwrap();
+    for board in 0..9 {
+        if let Some(player) = u.meta.player_at(board) {
+            writeln!(buf, "Board {} won by {}", board + 1, MARKS[player as usize]).unwrap();
+        }
+    }
+    match (u.pending_cycle(), u.target()) {
+        (Some(board), _) => writeln!(buf, "Board {} has a cycle to collapse", board + 1),
+        (None, Some(board)) => writeln!(buf, "Next move is in board {}", board + 1),
+        (None, None) => writeln!(buf, "Next move can be in any open board"),
+    }
+    .unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantum(board: u8, sq1: u8, sq2: u8) -> UltimateMove {
+        UltimateMove {
+            board,
+            mov: Move::Quantum(sq1, sq2),
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let mut u = UltimateBoard::new();
+        let mut v = Vec::new();
+        u.valid_moves(&mut v);
+        // 36 square pairs in both orders, in every board
+        assert_eq!(v.len(), 9 * 72);
+        u.do_move(quantum(4, 2, 6));
+        assert_eq!(u.target(), Some(2));
+        assert!(!u.is_valid(quantum(4, 0, 1)));
+        u.valid_moves(&mut v);
+        assert_eq!(v.len(), 72);
+        assert!(v.iter().all(|m| m.board == 2));
+
+        // X went first in board 4, then O's first move in board 2 is still O's mark
+        u.do_move(quantum(2, 4, 0));
+        assert!(u.board(2).quantum().is(1, 4));
+        assert_eq!(u.board(2).player_of(1), 1);
+        // and X going back to board 4 doesn't need a skip, it's X's turn there anyway
+        u.do_move(quantum(4, 0, 1));
+        assert!(u.board(4).quantum().is(2, 0));
+        // O plays in board 0 for the first time, skipping move 0 there
+        u.do_move(quantum(0, 0, 1));
+        assert!(u.board(0).quantum().is(1, 0));
+        assert!(!u.board(0).quantum().is(0, 0));
+    }
+
+    #[test]
+    fn cycles_come_first() {
+        let mut u = UltimateBoard::new();
+        u.do_move(quantum(0, 0, 1));
+        u.do_move(quantum(0, 1, 0));
+        // O closed a cycle in board 0 and sent X to board 1, but X has to collapse it first
+        assert_eq!(u.pending_cycle(), Some(0));
+        assert_eq!(u.target(), Some(1));
+        assert!(!u.is_valid(quantum(1, 0, 1)));
+        let mut v = Vec::new();
+        u.valid_moves(&mut v);
+        assert_eq!(v.len(), 2);
+        let collapse = UltimateMove {
+            board: 0,
+            mov: Move::Collapse { sq: 0, mov: 0 },
+        };
+        assert!(u.is_valid(collapse));
+        u.do_move(collapse);
+        assert_eq!(u.to_move(), 0);
+        assert!(u.board(0).classic().is_x(0) && u.board(0).classic().is_o(1));
+        // then X makes their own move where O sent them
+        u.valid_moves(&mut v);
+        assert!(v.iter().all(|m| m.board == 1));
+        u.do_move(quantum(1, 3, 4));
+        assert_eq!(u.to_move(), 1);
+    }
+
+    #[test]
+    fn meta_wins() {
+        let mut u = UltimateBoard::new();
+        // won sub-boards send moves anywhere, and a row of them wins the game
+        for board in [0, 4].iter() {
+            u.meta.set(*board, 0);
+        }
+        assert_eq!(u.outcome(), None);
+        u.meta.set(8, 0);
+        assert_eq!(u.outcome(), Some(Outcome::Win(0)));
+        let mut v = Vec::new();
+        u.valid_moves(&mut v);
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn random_games() {
+        let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+        let mut v = Vec::new();
+        for _game in 0..50 {
+            let mut u = UltimateBoard::new();
+            u.valid_moves(&mut v);
+            while !v.is_empty() {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                let m = v[rng as usize % v.len()];
+                let player = u.to_move();
+                u.do_move(m);
+                let b = u.board(m.board);
+                assert!(b.is_state_valid());
+                // every mark put down belongs to the player who made the move
+                if let Move::Quantum(sq, _) = m.mov {
+                    let mov = b.next_mov() - 1;
+                    assert!(b.quantum().is(mov, sq) || !b.classic().is_empty(sq));
+                    assert_eq!(b.player_of(mov), player);
+                }
+                assert!(b.next_mov() <= 18);
+                u.valid_moves(&mut v);
+            }
+            assert!(u.outcome().is_some());
+            assert!(render(&u).contains("||"));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{ClassicalBoardState, QuantumBoardState, MAX_PLAYERS};
+use smallvec::SmallVec;
+
+/// Iterates over every classical board the pending quantum moves could still turn into
+///
+/// Each pending move ends up in one of its two squares, and no square gets more than one move.
+/// The worlds are found by backtracking, so only consistent ones are ever built.
+#[derive(Clone, Debug)]
+pub struct Worlds {
+    base: ClassicalBoardState,
+    // the player of each pending move and the two squares it could end up in
+    moves: SmallVec<[(u8, [u8; 2]); 9]>,
+    // for each move, which of its squares to try next, 2 means both have been tried
+    choice: SmallVec<[u8; 9]>,
+    // the square each of the first `depth` moves is currently placed in
+    placed: SmallVec<[u8; 9]>,
+    depth: usize,
+    used: u32,
+    started: bool,
+    done: bool,
+}
+
+impl Worlds {
+    pub fn new<const N: usize>(
+        base: ClassicalBoardState,
+        q: &QuantumBoardState<N>,
+        next_mov: u8,
+        players: u8,
+    ) -> Self {
+        let mut moves = SmallVec::new();
+        for mov in 0..next_mov {
+            let mut sqs = (0..base.topology().cells()).filter(|&sq| q.is(mov, sq));
+            if let (Some(sq1), Some(sq2)) = (sqs.next(), sqs.next()) {
+                moves.push((mov % players, [sq1, sq2]));
+            }
+        }
+        let len = moves.len();
+        Worlds {
+            base,
+            moves,
+            choice: SmallVec::from_elem(0, len),
+            placed: SmallVec::from_elem(0, len),
+            depth: 0,
+            used: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    // takes the last placed move back off the board
+    fn backtrack(&mut self) {
+        self.depth -= 1;
+        self.used &= !(1 << self.placed[self.depth]);
+    }
+
+    fn build(&self) -> ClassicalBoardState {
+        let mut c = self.base;
+        for (&(player, _), &sq) in self.moves.iter().zip(self.placed.iter()) {
+            c.set(sq, player);
+        }
+        c
+    }
+}
+
+impl Iterator for Worlds {
+    type Item = ClassicalBoardState;
+
+    fn next(&mut self) -> Option<ClassicalBoardState> {
+        if self.done {
+            return None;
+        }
+        // the last call returned a full world, so start looking from the last move again
+        if self.started && self.depth == self.moves.len() {
+            if self.depth == 0 {
+                self.done = true;
+                return None;
+            }
+            self.backtrack();
+        }
+        self.started = true;
+        loop {
+            if self.depth == self.moves.len() {
+                return Some(self.build());
+            }
+            let d = self.depth;
+            if self.choice[d] == 2 {
+                // both squares tried, so go back and move the one before
+                self.choice[d] = 0;
+                if d == 0 {
+                    self.done = true;
+                    return None;
+                }
+                self.backtrack();
+                continue;
+            }
+            let sq = self.moves[d].1[self.choice[d] as usize];
+            self.choice[d] += 1;
+            if self.used & (1 << sq) == 0 {
+                self.used |= 1 << sq;
+                self.placed[d] = sq;
+                self.depth += 1;
+            }
+        }
+    }
+}
+
+/// How often each square ends up as each player's mark across all the possible worlds
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorldStats {
+    pub worlds: u64,
+    // indexed by square, then by player
+    pub counts: Vec<[u64; MAX_PLAYERS]>,
+}
+
+impl WorldStats {
+    pub fn from_worlds(worlds: impl Iterator<Item = ClassicalBoardState>) -> Self {
+        let mut stats = WorldStats::default();
+        for c in worlds {
+            let cells = c.topology().cells();
+            stats.counts.resize(cells as usize, [0; MAX_PLAYERS]);
+            stats.worlds += 1;
+            for sq in 0..cells {
+                if let Some(player) = c.player_at(sq) {
+                    stats.counts[sq as usize][player as usize] += 1;
+                }
+            }
+        }
+        stats
+    }
+
+    // the fraction of worlds in which the square is `player`'s, every world counting the same
+    pub fn fraction(&self, sq: u8, player: u8) -> f64 {
+        self.counts[sq as usize][player as usize] as f64 / self.worlds as f64
+    }
+
+    pub fn x_fraction(&self, sq: u8) -> f64 {
+        self.fraction(sq, 0)
+    }
+
+    pub fn o_fraction(&self, sq: u8) -> f64 {
+        self.fraction(sq, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BoardState, Move};
+
+    fn board(moves: &[(u8, u8)]) -> BoardState {
+        let mut b = BoardState::new();
+        for &(sq1, sq2) in moves {
+            b.do_move(Move::Quantum(sq1, sq2));
+        }
+        b
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(board(&[]).worlds().count(), 1);
+        assert_eq!(board(&[(0, 1)]).worlds().count(), 2);
+        // both moves can't be in square 1
+        assert_eq!(board(&[(0, 1), (1, 2)]).worlds().count(), 3);
+        // two independent moves
+        assert_eq!(board(&[(0, 1), (3, 4)]).worlds().count(), 4);
+        // a pending cycle only has its two collapses
+        assert_eq!(board(&[(0, 1), (1, 2), (2, 0)]).worlds().count(), 2);
+        assert_eq!(board(&[(0, 1), (0, 1)]).worlds().count(), 2);
+    }
+
+    #[test]
+    fn worlds_are_consistent() {
+        let mut b = board(&[(0, 1), (1, 2), (3, 4), (4, 3)]);
+        b.do_move(Move::Collapse { sq: 3, mov: 2 });
+        b.do_move(Move::Quantum(5, 6));
+        b.do_move(Move::Quantum(6, 2));
+        for c in b.worlds() {
+            // the collapsed squares stay as they were
+            assert!(c.is_x(3));
+            assert!(c.is_o(4));
+            // one square per pending move, on top of the classical ones
+            let filled = (0..9).filter(|&sq| !c.is_empty(sq)).count();
+            assert_eq!(filled, 6);
+            assert!(c.is_x(0) || c.is_x(1));
+            assert!(c.is_o(1) || c.is_o(2));
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let stats = board(&[(0, 1), (1, 2)]).world_stats();
+        assert_eq!(stats.worlds, 3);
+        // X1 is in square 0 in two of the three worlds
+        assert_eq!(stats.counts[0], [2, 0, 0]);
+        assert_eq!(stats.counts[1], [1, 1, 0]);
+        assert_eq!(stats.counts[2], [0, 2, 0]);
+        assert_eq!(stats.counts[5], [0, 0, 0]);
+        assert!((stats.x_fraction(0) - 2.0 / 3.0).abs() < 1e-9);
+        assert!((stats.o_fraction(2) - 2.0 / 3.0).abs() < 1e-9);
+    }
+}